@@ -12,6 +12,7 @@ enum EPOLL_EVENTS {
   EPOLLOUT     = (int) (1U << 1),
   EPOLLHUP     = (int) (1U << 2),
   EPOLLERR     = (int) (1U << 6),
+  EPOLLRDHUP   = (int) (1U << 13),
   EPOLLET      = (int) (1U << 8),
   EPOLLONESHOT = (int) (1U << 9)
 };
@@ -20,6 +21,7 @@ enum EPOLL_EVENTS {
 #define EPOLLOUT     (1U << 1)
 #define EPOLLHUP     (1U << 2)
 #define EPOLLERR     (1U << 6)
+#define EPOLLRDHUP   (1U << 13)
 #define EPOLLET      (1U << 8)
 #define EPOLLONESHOT (1U << 9)
 
@@ -27,6 +29,13 @@ enum EPOLL_EVENTS {
 #define EPOLL_CTL_MOD 2
 #define EPOLL_CTL_DEL 3
 
+#ifndef SOCK_NOTIFY_TRIGGER_ONESHOT
+#define SOCK_NOTIFY_TRIGGER_ONESHOT    (1 << 0)
+#define SOCK_NOTIFY_TRIGGER_PERSISTENT (1 << 1)
+#define SOCK_NOTIFY_TRIGGER_LEVEL      (1 << 2)
+#define SOCK_NOTIFY_TRIGGER_EDGE       (1 << 3)
+#endif
+
 /* clang-format on */
 
 typedef void* HANDLE;
@@ -43,12 +52,19 @@ typedef union epoll_data {
   HANDLE hnd;  /* Windows specific */
 } epoll_data_t;
 
+#ifdef WEPOLL_C_COMPAT /* Built with the `c-compat` feature. */
+struct epoll_event {
+  uint32_t events;   /* Epoll events and flags */
+  epoll_data_t data; /* User data variable */
+};
+#else
 struct epoll_event {
   epoll_data_t data; /* User data variable */
   void* __overlapped;
   size_t __internal;
   uint32_t events; /* Epoll events and flags */
 };
+#endif
 
 #ifdef __cplusplus
 extern "C" {
@@ -60,6 +76,15 @@ HANDLE epoll_create1(int flags);
 int epoll_close(HANDLE ephnd);
 
 int epoll_ctl(HANDLE ephnd, int op, HANDLE handle, struct epoll_event* event);
+int epoll_ctl2(HANDLE ephnd,
+               int op,
+               HANDLE handle,
+               struct epoll_event* event,
+               int trigger_flags);
+int epoll_ctl_try(HANDLE ephnd,
+                  int op,
+                  HANDLE handle,
+                  struct epoll_event* event);
 
 int epoll_wait(HANDLE ephnd,
                struct epoll_event* events,
@@ -76,6 +101,9 @@ int epoll_pwait2(HANDLE ephnd,
                  struct timespec* timeout,
                  bool alertable);
 
+int epoll_post(HANDLE ephnd, const struct epoll_event* event);
+int epoll_pending(HANDLE ephnd);
+
 #ifdef __cplusplus
 } /* extern "C" */
 #endif
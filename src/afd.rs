@@ -0,0 +1,452 @@
+//! Poll sockets with `\Device\Afd`, the technique of the original wepoll.
+//!
+//! Each registered socket has at most one `IOCTL_AFD_POLL` request pending
+//! on a shared AFD handle, which is associated with the port. The request is
+//! one-shot by nature. The `Level` mode is emulated by submitting the request
+//! again after its completion is received, and the edge modes are not
+//! supported.
+
+use alloc::boxed::Box;
+use core::{
+    ffi::c_void,
+    mem::{MaybeUninit, size_of},
+    ptr::{null, null_mut},
+    time::Duration,
+};
+
+use windows_sys::{
+    Wdk::Foundation::OBJECT_ATTRIBUTES,
+    Win32::{
+        Foundation::{
+            ERROR_NOT_ENOUGH_MEMORY, ERROR_NOT_SUPPORTED, HANDLE, NTSTATUS, RtlNtStatusToDosError,
+            STATUS_CANCELLED, STATUS_NOT_FOUND, STATUS_PENDING, STATUS_SUCCESS, UNICODE_STRING,
+        },
+        Networking::WinSock::{
+            SIO_BASE_HANDLE, SOCK_NOTIFY_EVENT_ERR, SOCK_NOTIFY_EVENT_HANGUP, SOCK_NOTIFY_EVENT_IN,
+            SOCK_NOTIFY_EVENT_OUT, SOCKET, SOCKET_ERROR, WSAIoctl,
+        },
+        System::IO::{CreateIoCompletionPort, IO_STATUS_BLOCK, OVERLAPPED_ENTRY},
+    },
+};
+
+use crate::{
+    Error, OwnedHandle, PollMode, Result, SourceAttr, backend::SocketBackend, lock::RwLock,
+    map::HashMap, map_try_reserve_error, notify::ascii_to_wide, stats::Counters,
+    wait::check_status,
+};
+
+#[link(name = "ntdll")]
+unsafe extern "system" {
+    fn NtCreateFile(
+        FileHandle: *mut HANDLE,
+        DesiredAccess: u32,
+        ObjectAttributes: *const OBJECT_ATTRIBUTES,
+        IoStatusBlock: *mut IO_STATUS_BLOCK,
+        AllocationSize: *const i64,
+        FileAttributes: u32,
+        ShareAccess: u32,
+        CreateDisposition: u32,
+        CreateOptions: u32,
+        EaBuffer: *const c_void,
+        EaLength: u32,
+    ) -> NTSTATUS;
+
+    fn NtDeviceIoControlFile(
+        FileHandle: HANDLE,
+        Event: HANDLE,
+        ApcRoutine: *const c_void,
+        ApcContext: *const c_void,
+        IoStatusBlock: *mut IO_STATUS_BLOCK,
+        IoControlCode: u32,
+        InputBuffer: *const c_void,
+        InputBufferLength: u32,
+        OutputBuffer: *mut c_void,
+        OutputBufferLength: u32,
+    ) -> NTSTATUS;
+
+    fn NtCancelIoFileEx(
+        FileHandle: HANDLE,
+        IoRequestToCancel: *const IO_STATUS_BLOCK,
+        IoStatusBlock: *mut IO_STATUS_BLOCK,
+    ) -> NTSTATUS;
+}
+
+const SYNCHRONIZE: u32 = 0x0010_0000;
+const FILE_SHARE_READ: u32 = 0x1;
+const FILE_SHARE_WRITE: u32 = 0x2;
+const FILE_OPEN: u32 = 0x1;
+
+const IOCTL_AFD_POLL: u32 = 0x0001_2024;
+
+const AFD_POLL_RECEIVE: u32 = 0x0001;
+const AFD_POLL_SEND: u32 = 0x0004;
+const AFD_POLL_DISCONNECT: u32 = 0x0008;
+const AFD_POLL_ABORT: u32 = 0x0010;
+const AFD_POLL_LOCAL_CLOSE: u32 = 0x0020;
+const AFD_POLL_ACCEPT: u32 = 0x0080;
+const AFD_POLL_CONNECT_FAIL: u32 = 0x0100;
+
+/// Any name under `\Device\Afd` opens the driver.
+const AFD_NAME: &[u16] = &ascii_to_wide(b"\\Device\\Afd\\Wepoll2");
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct AfdPollHandleInfo {
+    handle: HANDLE,
+    events: u32,
+    status: NTSTATUS,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct AfdPollInfo {
+    timeout: i64,
+    number_of_handles: u32,
+    exclusive: u32,
+    handles: [AfdPollHandleInfo; 1],
+}
+
+/// The state of a registered socket.
+///
+/// It is boxed, and its address is the APC context of the poll request. The
+/// fields `iosb` and `info` are written by the system while the request is
+/// pending, so the state is only accessed through raw pointers.
+struct PollState {
+    iosb: IO_STATUS_BLOCK,
+    info: AfdPollInfo,
+    socket: SOCKET,
+    /// The base service provider handle of the socket.
+    base: SOCKET,
+    attr: SourceAttr,
+    /// The AFD events of the pending request.
+    polled: u32,
+    /// A poll request is submitted and its completion is not received yet.
+    pending: bool,
+    /// The registration is removed. The state is freed when the completion
+    /// of the pending request is received.
+    removed: bool,
+}
+
+/// The backend with `\Device\Afd`, for the systems without
+/// `ProcessSocketNotifications`.
+#[derive(Debug)]
+pub struct AfdBackend {
+    /// The AFD handle, which is associated with the port with its own value
+    /// as the completion key.
+    handle: OwnedHandle,
+    /// The states of the registered sockets.
+    states: RwLock<HashMap<SOCKET, *mut PollState>>,
+}
+
+unsafe impl Send for AfdBackend {}
+unsafe impl Sync for AfdBackend {}
+
+impl AfdBackend {
+    /// Opens an AFD handle and associates it with the port.
+    pub fn new(port: HANDLE) -> Result<Self> {
+        let name = UNICODE_STRING {
+            Length: size_of_val(AFD_NAME) as _,
+            MaximumLength: size_of_val(AFD_NAME) as _,
+            Buffer: AFD_NAME.as_ptr().cast_mut(),
+        };
+        let attributes = OBJECT_ATTRIBUTES {
+            Length: size_of::<OBJECT_ATTRIBUTES>() as _,
+            RootDirectory: null_mut(),
+            ObjectName: &name,
+            Attributes: 0,
+            SecurityDescriptor: null(),
+            SecurityQualityOfService: null(),
+        };
+        let mut handle = null_mut();
+        let mut iosb = MaybeUninit::<IO_STATUS_BLOCK>::uninit();
+        check_status(
+            unsafe {
+                NtCreateFile(
+                    &mut handle,
+                    SYNCHRONIZE,
+                    &attributes,
+                    iosb.as_mut_ptr(),
+                    null(),
+                    0,
+                    FILE_SHARE_READ | FILE_SHARE_WRITE,
+                    FILE_OPEN,
+                    0,
+                    null(),
+                    0,
+                )
+            },
+            "NtCreateFile",
+        )?;
+        let handle = unsafe { OwnedHandle::from_raw_handle(handle) };
+        let res = unsafe {
+            CreateIoCompletionPort(handle.as_raw_handle(), port, handle.as_raw_handle() as _, 0)
+        };
+        if res.is_null() {
+            return Err(Error::last_os_error().with_context("CreateIoCompletionPort"));
+        }
+        Ok(Self {
+            handle,
+            states: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// The completion key of the poll requests.
+    fn key(&self) -> usize {
+        self.handle.as_raw_handle() as _
+    }
+
+    /// Submits a poll request with the current interest.
+    ///
+    /// # Safety
+    ///
+    /// The state should be valid and not pending.
+    unsafe fn submit(&self, state: *mut PollState) -> Result<()> {
+        unsafe {
+            debug_assert!(!(*state).pending);
+            let events = interest_to_afd((*state).attr);
+            (*state).info = AfdPollInfo {
+                timeout: i64::MAX,
+                number_of_handles: 1,
+                exclusive: 0,
+                handles: [AfdPollHandleInfo {
+                    handle: (*state).base as _,
+                    events,
+                    status: STATUS_SUCCESS,
+                }],
+            };
+            (*state).iosb.Anonymous.Status = STATUS_PENDING;
+            let info = &raw mut (*state).info;
+            let status = NtDeviceIoControlFile(
+                self.handle.as_raw_handle(),
+                null_mut(),
+                null(),
+                state.cast(),
+                &raw mut (*state).iosb,
+                IOCTL_AFD_POLL,
+                info.cast(),
+                size_of::<AfdPollInfo>() as _,
+                info.cast(),
+                size_of::<AfdPollInfo>() as _,
+            );
+            match status {
+                // The completion is queued to the port even if it succeeds
+                // immediately.
+                STATUS_SUCCESS | STATUS_PENDING => {
+                    (*state).pending = true;
+                    (*state).polled = events;
+                    Ok(())
+                }
+                _ => {
+                    Err(Error::new(RtlNtStatusToDosError(status))
+                        .with_context("NtDeviceIoControlFile"))
+                }
+            }
+        }
+    }
+
+    /// Cancels the pending poll request. The completion is still queued.
+    ///
+    /// # Safety
+    ///
+    /// The state should be valid and pending.
+    unsafe fn cancel(&self, state: *mut PollState) -> Result<()> {
+        let mut iosb = MaybeUninit::<IO_STATUS_BLOCK>::uninit();
+        let status = unsafe {
+            NtCancelIoFileEx(
+                self.handle.as_raw_handle(),
+                &raw const (*state).iosb,
+                iosb.as_mut_ptr(),
+            )
+        };
+        match status {
+            // The request has completed, and its completion is queued.
+            STATUS_NOT_FOUND => Ok(()),
+            _ => check_status(status, "NtCancelIoFileEx"),
+        }
+    }
+}
+
+impl SocketBackend for AfdBackend {
+    fn update(&self, _port: HANDLE, socket: SOCKET, attr: &SourceAttr) -> Result<()> {
+        if !matches!(attr.mode, PollMode::Oneshot | PollMode::Level) {
+            return Err(Error::new(ERROR_NOT_SUPPORTED));
+        }
+        let mut states = self.states.write();
+        let state = match states.get(&socket) {
+            Some(state) => *state,
+            None => {
+                let state = Box::try_new(PollState {
+                    iosb: unsafe { MaybeUninit::zeroed().assume_init() },
+                    info: unsafe { MaybeUninit::zeroed().assume_init() },
+                    socket,
+                    base: base_socket(socket)?,
+                    attr: *attr,
+                    polled: 0,
+                    pending: false,
+                    removed: false,
+                })
+                .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+                let state = Box::into_raw(state);
+                if let Err(e) = states.try_insert(socket, state) {
+                    drop(unsafe { Box::from_raw(state) });
+                    return Err(map_try_reserve_error(e));
+                }
+                state
+            }
+        };
+        unsafe {
+            (*state).attr = *attr;
+            if (*state).pending {
+                // The request is submitted again when the cancellation is
+                // received.
+                if (*state).polled != interest_to_afd(*attr) {
+                    self.cancel(state)?;
+                }
+                Ok(())
+            } else if attr.interest.is_empty() {
+                Ok(())
+            } else {
+                self.submit(state)
+            }
+        }
+    }
+
+    fn remove(
+        &self,
+        _port: HANDLE,
+        socket: SOCKET,
+        _key: usize,
+        _timeout: Duration,
+        _stats: &Counters,
+    ) -> Result<()> {
+        let mut states = self.states.write();
+        let Some(state) = states.remove(&socket) else {
+            return Ok(());
+        };
+        unsafe {
+            if (*state).pending {
+                // No need to wait: the completion is dropped when received.
+                (*state).removed = true;
+                self.cancel(state)
+            } else {
+                drop(Box::from_raw(state));
+                Ok(())
+            }
+        }
+    }
+
+    fn process(&self, entry: &mut OVERLAPPED_ENTRY) -> bool {
+        if entry.lpCompletionKey != self.key() || entry.lpOverlapped.is_null() {
+            return true;
+        }
+        let state = entry.lpOverlapped.cast::<PollState>();
+        let mut states = self.states.write();
+        unsafe {
+            (*state).pending = false;
+            if (*state).removed {
+                drop(Box::from_raw(state));
+                return false;
+            }
+            let attr = (*state).attr;
+            let status = (*state).iosb.Anonymous.Status;
+            let events = if status == STATUS_CANCELLED {
+                0
+            } else if status < 0 {
+                SOCK_NOTIFY_EVENT_ERR
+            } else {
+                let afd_events = (*state).info.handles[0].events;
+                if afd_events & AFD_POLL_LOCAL_CLOSE != 0 {
+                    // The socket is closed, and the registration is gone.
+                    states.remove(&(*state).socket);
+                    drop(Box::from_raw(state));
+                    return false;
+                }
+                afd_to_events(afd_events) & (attr.interest.bits() | SOCK_NOTIFY_EVENT_ERR)
+            };
+            if (events == 0 || attr.mode == PollMode::Level) && !attr.interest.is_empty() {
+                // The event is received anyway. If it fails, the socket is
+                // just not triggered again.
+                self.submit(state).ok();
+            }
+            if events == 0 {
+                return false;
+            }
+            entry.lpCompletionKey = attr.key;
+            entry.lpOverlapped = null_mut();
+            entry.Internal = status as _;
+            entry.dwNumberOfBytesTransferred = events;
+        }
+        true
+    }
+}
+
+impl Drop for AfdBackend {
+    fn drop(&mut self) {
+        // Cancel all pending requests of the handle.
+        let mut iosb = MaybeUninit::<IO_STATUS_BLOCK>::uninit();
+        unsafe { NtCancelIoFileEx(self.handle.as_raw_handle(), null(), iosb.as_mut_ptr()) };
+        // The pending requests may still write to their states after being
+        // cancelled, so the states are leaked instead of freed. So are the
+        // removed ones whose completions are not received.
+        for (_, state) in self.states.get_mut().drain() {
+            if unsafe { !(*state).pending } {
+                drop(unsafe { Box::from_raw(state) });
+            }
+        }
+    }
+}
+
+/// Gets the base service provider handle, which is required by AFD.
+fn base_socket(socket: SOCKET) -> Result<SOCKET> {
+    let mut base: SOCKET = 0;
+    let mut bytes = 0;
+    let res = unsafe {
+        WSAIoctl(
+            socket,
+            SIO_BASE_HANDLE,
+            null(),
+            0,
+            (&raw mut base).cast(),
+            size_of::<SOCKET>() as _,
+            &mut bytes,
+            null_mut(),
+            None,
+        )
+    };
+    if res == SOCKET_ERROR {
+        Err(Error::last_os_error().with_context("WSAIoctl"))
+    } else {
+        Ok(base)
+    }
+}
+
+fn interest_to_afd(attr: SourceAttr) -> u32 {
+    let mut events = AFD_POLL_CONNECT_FAIL | AFD_POLL_LOCAL_CLOSE;
+    if attr.interest.is_readable() {
+        events |= AFD_POLL_RECEIVE | AFD_POLL_ACCEPT | AFD_POLL_DISCONNECT;
+    }
+    if attr.interest.is_writable() {
+        events |= AFD_POLL_SEND;
+    }
+    if attr.interest.is_hangup() {
+        events |= AFD_POLL_DISCONNECT | AFD_POLL_ABORT;
+    }
+    events
+}
+
+fn afd_to_events(afd_events: u32) -> u32 {
+    let mut events = 0;
+    if afd_events & (AFD_POLL_RECEIVE | AFD_POLL_ACCEPT | AFD_POLL_DISCONNECT) != 0 {
+        events |= SOCK_NOTIFY_EVENT_IN;
+    }
+    if afd_events & AFD_POLL_SEND != 0 {
+        events |= SOCK_NOTIFY_EVENT_OUT;
+    }
+    if afd_events & (AFD_POLL_DISCONNECT | AFD_POLL_ABORT) != 0 {
+        events |= SOCK_NOTIFY_EVENT_HANGUP;
+    }
+    if afd_events & AFD_POLL_CONNECT_FAIL != 0 {
+        events |= SOCK_NOTIFY_EVENT_ERR;
+    }
+    events
+}
@@ -0,0 +1,445 @@
+//! The backends registering sockets to the port.
+//!
+//! [`NotifyBackend`] uses `ProcessSocketNotifications`. When it is not
+//! available, and the feature `afd` is enabled, `AfdBackend` polls the
+//! sockets with `\Device\Afd` instead.
+
+use alloc::vec::Vec;
+use core::{mem::MaybeUninit, ptr::null_mut, time::Duration};
+
+use windows_sys::Win32::{
+    Foundation::{ERROR_SUCCESS, HANDLE, WAIT_TIMEOUT},
+    Networking::WinSock::{
+        SOCK_NOTIFY_EVENT_REMOVE, SOCK_NOTIFY_OP_DISABLE, SOCK_NOTIFY_OP_ENABLE,
+        SOCK_NOTIFY_OP_REMOVE, SOCK_NOTIFY_REGISTER_EVENT_HANGUP, SOCK_NOTIFY_REGISTER_EVENT_IN,
+        SOCK_NOTIFY_REGISTER_EVENT_NONE, SOCK_NOTIFY_REGISTER_EVENT_OUT, SOCK_NOTIFY_REGISTRATION,
+        SOCK_NOTIFY_TRIGGER_EDGE, SOCK_NOTIFY_TRIGGER_LEVEL, SOCK_NOTIFY_TRIGGER_ONESHOT,
+        SOCK_NOTIFY_TRIGGER_PERSISTENT, SOCKET,
+    },
+    System::{IO::OVERLAPPED_ENTRY, SystemInformation::GetTickCount64},
+};
+
+#[cfg(feature = "afd")]
+pub use crate::afd::AfdBackend;
+use crate::{
+    Error, Interest, PollMode, Result, SourceAttr,
+    lock::RwLock,
+    map::HashMap,
+    map_try_reserve_error,
+    notify::ProcessSocketNotifications,
+    repost,
+    stats::{Counter, Counters},
+};
+
+/// Registers sockets to the port, and translates their completion entries.
+pub trait SocketBackend {
+    /// Enables the registration of the socket with the attributes, or updates
+    /// it in place. The registration is disabled if the interest is empty.
+    fn update(&self, port: HANDLE, socket: SOCKET, attr: &SourceAttr) -> Result<()>;
+
+    /// Removes the registration of the socket, and waits at most `timeout`
+    /// until no more events of it will be received.
+    fn remove(
+        &self,
+        port: HANDLE,
+        socket: SOCKET,
+        key: usize,
+        timeout: Duration,
+        stats: &Counters,
+    ) -> Result<()>;
+
+    /// Processes a received entry in place. Returns `false` if the entry is
+    /// internal to the backend, and should not be delivered.
+    fn process(&self, entry: &mut OVERLAPPED_ENTRY) -> bool;
+}
+
+/// The backend with `ProcessSocketNotifications`.
+#[derive(Debug)]
+pub struct NotifyBackend {
+    /// The removals waiting for their `SOCK_NOTIFY_EVENT_REMOVE` events, keyed
+    /// by the completion keys.
+    ///
+    /// Other threads may be waiting on the port meanwhile, and receive the
+    /// events instead. They are handed over to the removals here, and not
+    /// delivered.
+    removals: RwLock<HashMap<usize, Removal>>,
+}
+
+/// The removals of the same key.
+#[derive(Debug)]
+struct Removal {
+    /// Number of the removals waiting.
+    waiting: usize,
+    /// Number of the remove events received by other waits, and not taken
+    /// yet.
+    received: usize,
+}
+
+impl NotifyBackend {
+    pub const fn new() -> Self {
+        Self {
+            removals: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Starts waiting for a remove event of the key.
+    fn begin_remove(&self, key: usize) -> Result<()> {
+        let mut removals = self.removals.write();
+        match removals.get_mut(&key) {
+            Some(removal) => removal.waiting += 1,
+            None => {
+                removals
+                    .try_insert(
+                        key,
+                        Removal {
+                            waiting: 1,
+                            received: 0,
+                        },
+                    )
+                    .map_err(map_try_reserve_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops waiting for a remove event of the key.
+    fn end_remove(&self, key: usize) {
+        let mut removals = self.removals.write();
+        if let Some(removal) = removals.get_mut(&key) {
+            removal.waiting -= 1;
+            if removal.waiting == 0 {
+                // The events received later are delivered as is.
+                removals.remove(&key);
+            }
+        }
+    }
+
+    /// Hands over the entry if it is a remove event waited for. Returns
+    /// `false` if it is not.
+    fn receive_remove(&self, entry: &OVERLAPPED_ENTRY) -> bool {
+        // The socket events don't carry an `OVERLAPPED`.
+        if !entry.lpOverlapped.is_null()
+            || (entry.dwNumberOfBytesTransferred & SOCK_NOTIFY_EVENT_REMOVE) == 0
+        {
+            return false;
+        }
+        match self.removals.write().get_mut(&entry.lpCompletionKey) {
+            Some(removal) => {
+                removal.received += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Takes a remove event of the key received. The sockets sharing the same
+    /// key are not distinguished, but each removal takes one event.
+    fn take_remove(&self, key: usize) -> bool {
+        match self.removals.write().get_mut(&key) {
+            Some(removal) if removal.received > 0 => {
+                removal.received -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl SocketBackend for NotifyBackend {
+    fn update(&self, port: HANDLE, socket: SOCKET, attr: &SourceAttr) -> Result<()> {
+        let reg = create_registration(socket, attr.key, attr.interest, attr.mode, true);
+        update_source(port, reg)
+    }
+
+    fn remove(
+        &self,
+        port: HANDLE,
+        socket: SOCKET,
+        key: usize,
+        timeout: Duration,
+        stats: &Counters,
+    ) -> Result<()> {
+        let reg = create_registration(socket, key, Interest::NONE, PollMode::Oneshot, false);
+        self.begin_remove(key)?;
+        let res = self.update_and_wait_for_remove(port, reg, key, timeout, stats);
+        self.end_remove(key);
+        res
+    }
+
+    fn process(&self, entry: &mut OVERLAPPED_ENTRY) -> bool {
+        // The entries are delivered by the system as is, except the remove
+        // events waited for by the removals.
+        !self.receive_remove(entry)
+    }
+}
+
+/// The backend selected when the poller is created.
+#[derive(Debug)]
+pub enum Backend {
+    /// See [`NotifyBackend`].
+    Notify(NotifyBackend),
+    /// See [`AfdBackend`].
+    #[cfg(feature = "afd")]
+    Afd(AfdBackend),
+}
+
+impl Backend {
+    /// Selects [`NotifyBackend`] if supported, otherwise [`AfdBackend`]. With
+    /// the feature `force-afd`, [`AfdBackend`] is always selected.
+    #[cfg(feature = "afd")]
+    pub fn new(port: HANDLE) -> Result<Self> {
+        if !cfg!(feature = "force-afd") && crate::notify::is_supported() {
+            Ok(Self::Notify(NotifyBackend::new()))
+        } else {
+            Self::afd(port)
+        }
+    }
+
+    /// Selects [`AfdBackend`] even if [`NotifyBackend`] is supported.
+    #[cfg(feature = "afd")]
+    pub fn afd(port: HANDLE) -> Result<Self> {
+        Ok(Self::Afd(AfdBackend::new(port)?))
+    }
+
+    /// Selects [`NotifyBackend`]. The socket operations fail with
+    /// `ERROR_NOT_SUPPORTED` if it is not supported.
+    #[cfg(not(feature = "afd"))]
+    pub fn new(_port: HANDLE) -> Result<Self> {
+        Ok(Self::Notify(NotifyBackend::new()))
+    }
+}
+
+impl SocketBackend for Backend {
+    fn update(&self, port: HANDLE, socket: SOCKET, attr: &SourceAttr) -> Result<()> {
+        match self {
+            Self::Notify(b) => b.update(port, socket, attr),
+            #[cfg(feature = "afd")]
+            Self::Afd(b) => b.update(port, socket, attr),
+        }
+    }
+
+    fn remove(
+        &self,
+        port: HANDLE,
+        socket: SOCKET,
+        key: usize,
+        timeout: Duration,
+        stats: &Counters,
+    ) -> Result<()> {
+        match self {
+            Self::Notify(b) => b.remove(port, socket, key, timeout, stats),
+            #[cfg(feature = "afd")]
+            Self::Afd(b) => b.remove(port, socket, key, timeout, stats),
+        }
+    }
+
+    fn process(&self, entry: &mut OVERLAPPED_ENTRY) -> bool {
+        match self {
+            Self::Notify(b) => b.process(entry),
+            #[cfg(feature = "afd")]
+            Self::Afd(b) => b.process(entry),
+        }
+    }
+}
+
+/// The context of the errors when registering.
+const REGISTER: &str = "ProcessSocketNotifications register";
+
+/// The context of the errors when removing.
+const REMOVE: &str = "ProcessSocketNotifications remove";
+
+/// The max time in milliseconds of each wait for the remove event, before
+/// checking whether it has been received by other threads.
+const REMOVE_POLL_INTERVAL: u64 = 10;
+
+/// Add or modify the registration.
+fn update_source(port: HANDLE, mut reg: SOCK_NOTIFY_REGISTRATION) -> Result<()> {
+    let res =
+        unsafe { ProcessSocketNotifications(port, 1, &mut reg, 0, 0, null_mut(), null_mut()) };
+    if res == ERROR_SUCCESS {
+        if reg.registrationResult == ERROR_SUCCESS {
+            Ok(())
+        } else {
+            Err(Error::new(reg.registrationResult).with_context(REGISTER))
+        }
+    } else {
+        Err(Error::new(res).with_context(REGISTER))
+    }
+}
+
+impl NotifyBackend {
+    /// Attempt to remove a registration, and wait for the
+    /// `SOCK_NOTIFY_EVENT_REMOVE` event.
+    ///
+    /// The unrelated entries received in the meantime are reposted in their
+    /// original order at last.
+    fn update_and_wait_for_remove(
+        &self,
+        port: HANDLE,
+        reg: SOCK_NOTIFY_REGISTRATION,
+        key: usize,
+        timeout: Duration,
+        stats: &Counters,
+    ) -> Result<()> {
+        let mut foreign = Vec::new();
+        let res =
+            self.update_and_wait_for_remove_buffered(port, reg, key, timeout, stats, &mut foreign);
+        // Try to repost all entries even if some of them fail.
+        let reposted = foreign
+            .into_iter()
+            .map(|entry| repost(port, entry))
+            .fold(Ok(()), Result::and);
+        res.and(reposted)
+    }
+
+    fn update_and_wait_for_remove_buffered(
+        &self,
+        port: HANDLE,
+        mut reg: SOCK_NOTIFY_REGISTRATION,
+        key: usize,
+        timeout: Duration,
+        stats: &Counters,
+        foreign: &mut Vec<OVERLAPPED_ENTRY>,
+    ) -> Result<()> {
+        debug_assert_eq!(reg.operation, SOCK_NOTIFY_OP_REMOVE as _);
+        let mut received = 0;
+        let mut entry: MaybeUninit<OVERLAPPED_ENTRY> = MaybeUninit::uninit();
+
+        // Hand over the remove events, including the wanted one. Buffer the
+        // other entries, or repost them immediately if out of memory.
+        let mut receive = |entry: OVERLAPPED_ENTRY| {
+            if self.receive_remove(&entry) {
+                return Ok(());
+            }
+            if entry.lpCompletionKey == key {
+                // The entry is current key but not the remove event, just ignore it.
+                return Ok(());
+            }
+            stats.add(Counter::RemoveReposts, 1);
+            if foreign.try_reserve(1).is_ok() {
+                foreign.push(entry);
+                Ok(())
+            } else {
+                crate::repost(port, entry)
+            }
+        };
+
+        // Update the registration and wait for the event in the same time.
+        // However, the returned completion entry may not be the wanted REMOVE event.
+        let res = unsafe {
+            ProcessSocketNotifications(
+                port,
+                1,
+                &mut reg,
+                0,
+                1,
+                entry.as_mut_ptr().cast(),
+                &mut received,
+            )
+        };
+        match res {
+            ERROR_SUCCESS | WAIT_TIMEOUT => {
+                if reg.registrationResult != ERROR_SUCCESS {
+                    // If the registration is not successful, the received entry should be reposted.
+                    if received == 1 {
+                        let entry = unsafe { entry.assume_init() };
+                        stats.add(Counter::RemoveReposts, 1);
+                        crate::repost(port, entry)?;
+                    }
+                    return Err(Error::new(reg.registrationResult).with_context(REMOVE));
+                }
+            }
+            _ => return Err(Error::new(res).with_context(REMOVE)),
+        }
+        if received == 1 {
+            // The registration is successful, and check the received entry.
+            receive(unsafe { entry.assume_init() })?;
+        }
+
+        // Wait for the event until timed out. It may be received by other
+        // threads waiting on the port, so check the handed over events
+        // between short waits.
+        let timeout = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+        let deadline = unsafe { GetTickCount64() }.saturating_add(timeout);
+        loop {
+            if self.take_remove(key) {
+                return Ok(());
+            }
+            let now = unsafe { GetTickCount64() };
+            if now >= deadline {
+                return Err(Error::new(WAIT_TIMEOUT).with_context(REMOVE));
+            }
+            stats.add(Counter::RemoveLoops, 1);
+            let timeout = (deadline - now).min(REMOVE_POLL_INTERVAL) as u32;
+            let res = unsafe {
+                ProcessSocketNotifications(
+                    port,
+                    0,
+                    null_mut(),
+                    timeout,
+                    1,
+                    entry.as_mut_ptr().cast(),
+                    &mut received,
+                )
+            };
+            match res {
+                ERROR_SUCCESS => {
+                    debug_assert_eq!(received, 1);
+                    receive(unsafe { entry.assume_init() })?;
+                }
+                WAIT_TIMEOUT => {}
+                _ => return Err(Error::new(res).with_context(REMOVE)),
+            }
+        }
+    }
+}
+
+fn interest_to_filter(interest: Interest) -> u16 {
+    let mut filter = SOCK_NOTIFY_REGISTER_EVENT_NONE;
+    if interest.is_readable() {
+        filter |= SOCK_NOTIFY_REGISTER_EVENT_IN;
+    }
+    if interest.is_writable() {
+        filter |= SOCK_NOTIFY_REGISTER_EVENT_OUT;
+    }
+    if interest.is_hangup() {
+        filter |= SOCK_NOTIFY_REGISTER_EVENT_HANGUP;
+    }
+    filter as _
+}
+
+fn mode_to_flags(mode: PollMode) -> u8 {
+    let flags = match mode {
+        PollMode::Oneshot => SOCK_NOTIFY_TRIGGER_ONESHOT | SOCK_NOTIFY_TRIGGER_LEVEL,
+        PollMode::Level => SOCK_NOTIFY_TRIGGER_PERSISTENT | SOCK_NOTIFY_TRIGGER_LEVEL,
+        PollMode::Edge => SOCK_NOTIFY_TRIGGER_PERSISTENT | SOCK_NOTIFY_TRIGGER_EDGE,
+        PollMode::EdgeOneshot => SOCK_NOTIFY_TRIGGER_ONESHOT | SOCK_NOTIFY_TRIGGER_EDGE,
+    };
+    flags as u8
+}
+
+fn create_registration(
+    socket: SOCKET,
+    key: usize,
+    interest: Interest,
+    mode: PollMode,
+    enable: bool,
+) -> SOCK_NOTIFY_REGISTRATION {
+    let filter = interest_to_filter(interest);
+    SOCK_NOTIFY_REGISTRATION {
+        socket,
+        completionKey: key as _,
+        eventFilter: filter,
+        operation: if enable {
+            if filter == SOCK_NOTIFY_REGISTER_EVENT_NONE as _ {
+                SOCK_NOTIFY_OP_DISABLE as _
+            } else {
+                SOCK_NOTIFY_OP_ENABLE as _
+            }
+        } else {
+            SOCK_NOTIFY_OP_REMOVE as _
+        },
+        triggerFlags: mode_to_flags(mode),
+        registrationResult: 0,
+    }
+}
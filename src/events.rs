@@ -0,0 +1,182 @@
+//! Collections of events received by [`Poller::wait_events`] and
+//! [`Poller::wait_into_ring`].
+//!
+//! [`Poller::wait_events`]: crate::Poller::wait_events
+//! [`Poller::wait_into_ring`]: crate::Poller::wait_into_ring
+
+use alloc::vec::Vec;
+use core::{mem::MaybeUninit, slice};
+
+use windows_sys::Win32::Foundation::ERROR_NOT_ENOUGH_MEMORY;
+
+use crate::{Error, Event, Result};
+
+/// A buffer of events with fixed capacity.
+///
+/// The default buffer has no capacity, and can't receive any event. Create it
+/// by [`Events::with_capacity`], which reports the allocation failure.
+#[derive(Default)]
+pub struct Events {
+    events: Vec<Event>,
+}
+
+impl Events {
+    /// Creates a buffer which could receive at most `capacity` events at a
+    /// time.
+    pub fn with_capacity(capacity: usize) -> Result<Self> {
+        let mut events = Vec::new();
+        events
+            .try_reserve_exact(capacity)
+            .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+        Ok(Self { events })
+    }
+
+    /// The max number of events could be received at a time.
+    pub fn capacity(&self) -> usize {
+        self.events.capacity()
+    }
+
+    /// Number of the received events.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Whether no event is received.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Clears the received events.
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// Iterates the received events.
+    pub fn iter(&self) -> slice::Iter<'_, Event> {
+        self.events.iter()
+    }
+
+    pub(crate) fn as_mut_vec(&mut self) -> &mut Vec<Event> {
+        &mut self.events
+    }
+}
+
+impl<'a> IntoIterator for &'a Events {
+    type IntoIter = slice::Iter<'a, Event>;
+    type Item = &'a Event;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// A ring buffer of events with fixed capacity.
+///
+/// The events are received into the free slots directly by
+/// [`Poller::wait_into_ring`], and consumed in order.
+///
+/// The default ring has no capacity, and can't hold any event. Create it by
+/// [`EventRing::with_capacity`], which reports the allocation failure.
+///
+/// [`Poller::wait_into_ring`]: crate::Poller::wait_into_ring
+#[derive(Default)]
+pub struct EventRing {
+    slots: Vec<MaybeUninit<Event>>,
+    /// The index of the first event.
+    head: usize,
+    /// Number of the events.
+    len: usize,
+}
+
+impl EventRing {
+    /// Creates a ring which could hold at most `capacity` events.
+    pub fn with_capacity(capacity: usize) -> Result<Self> {
+        let mut slots = Vec::new();
+        slots
+            .try_reserve_exact(capacity)
+            .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+        slots.resize(capacity, MaybeUninit::uninit());
+        Ok(Self {
+            slots,
+            head: 0,
+            len: 0,
+        })
+    }
+
+    /// The max number of events could be held.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Number of the events.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there is no event.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether there is no free slot.
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
+    /// Removes all events.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Removes the first event and returns it.
+    pub fn pop(&mut self) -> Option<Event> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = unsafe { self.slots[self.head].assume_init() };
+        self.head = (self.head + 1) % self.capacity();
+        self.len -= 1;
+        Some(event)
+    }
+
+    /// The events in order, as two contiguous slices.
+    pub fn as_slices(&self) -> (&[Event], &[Event]) {
+        let end = self.head + self.len;
+        let (first, second) = if end <= self.capacity() {
+            (&self.slots[self.head..end], &self.slots[..0])
+        } else {
+            (
+                &self.slots[self.head..],
+                &self.slots[..end - self.capacity()],
+            )
+        };
+        // SAFETY: the slots of the events are initialized.
+        unsafe { (first.assume_init_ref(), second.assume_init_ref()) }
+    }
+
+    /// The free slots in order, as two contiguous slices.
+    ///
+    /// The second one is not empty only if the free slots wrap around.
+    pub fn spare_slots(&mut self) -> (&mut [MaybeUninit<Event>], &mut [MaybeUninit<Event>]) {
+        let end = self.head + self.len;
+        if end < self.capacity() {
+            let (wrapped, tail) = self.slots.split_at_mut(end);
+            (tail, &mut wrapped[..self.head])
+        } else {
+            let start = end - self.capacity();
+            (&mut self.slots[start..self.head], &mut [])
+        }
+    }
+
+    /// Appends `count` events written to the free slots.
+    ///
+    /// # Safety
+    ///
+    /// The first `count` free slots in the order of [`EventRing::spare_slots`]
+    /// should be initialized.
+    pub unsafe fn commit(&mut self, count: usize) {
+        debug_assert!(count <= self.capacity() - self.len);
+        self.len += count;
+    }
+}
@@ -1,17 +1,36 @@
 //! FFI of this crate. Imitate epoll(2).
 
-use core::{
-    ffi::c_int,
-    ptr::{null, null_mut},
-    time::Duration,
-};
+use alloc::sync::Arc;
+use core::{ffi::c_int, ptr::null_mut, time::Duration};
+#[cfg(feature = "c-compat")]
+use core::{ffi::c_void, mem::MaybeUninit};
 
 use windows_sys::Win32::{
-    Foundation::{ERROR_INVALID_PARAMETER, HANDLE, HANDLE_PTR, SetLastError},
-    Networking::WinSock::{SOCKET, WSAENOTSOCK, WSAGetLastError, WSAGetQOSByName},
+    Foundation::{
+        ERROR_BUSY, ERROR_INVALID_HANDLE, ERROR_INVALID_PARAMETER, ERROR_NOT_ENOUGH_MEMORY, HANDLE,
+        HANDLE_PTR, INVALID_HANDLE_VALUE, STATUS_SUCCESS, SetLastError,
+    },
+    Networking::WinSock::SOCKET,
+};
+
+use crate::{
+    Error, Event, Interest, PollMode, Poller, Registry, Result, check_key, lock::RwLock,
+    map::HashMap,
 };
 
-use crate::{Error, Event, PollMode, Poller, Result, lock::RwLock, map::HashMap};
+/// Writes a line of the FFI call to the debugger with the feature `trace`.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "trace")]
+        crate::trace::output(format_args!($($arg)*));
+    };
+}
+
+/// The flags of the event to trace, if the pointer is valid.
+#[cfg(feature = "trace")]
+fn trace_events(event: *const epoll_event) -> Option<u32> {
+    check_pointer(event).ok().map(|event| event.events())
+}
 
 #[inline]
 fn io_result_ok<T>(res: Result<T>) -> Option<T> {
@@ -39,7 +58,7 @@ fn check_pointer<'a, T>(ptr: *const T) -> Result<&'a T> {
     if ptr.is_aligned() && !ptr.is_null() {
         Ok(unsafe { &*ptr })
     } else {
-        Err(Error(ERROR_INVALID_PARAMETER))
+        Err(Error::new(ERROR_INVALID_PARAMETER))
     }
 }
 
@@ -51,11 +70,27 @@ pub const EPOLLOUT: c_int = 1 << 1;
 pub const EPOLLHUP: c_int = 1 << 2;
 /// Error event.
 pub const EPOLLERR: c_int = 1 << 6;
+/// Read hangup event.
+///
+/// `ProcessSocketNotifications` has only one hangup event, which is raised
+/// when the peer shuts down the connection or its sending side. Therefore,
+/// registering `EPOLLRDHUP` is the same as registering `EPOLLHUP`, and both
+/// flags are reported together.
+pub const EPOLLRDHUP: c_int = 1 << 13;
 /// Edge trigger.
 pub const EPOLLET: c_int = 1 << 8;
 /// Oneshot trigger.
 pub const EPOLLONESHOT: c_int = 1 << 9;
 
+/// Trigger once, used by [`epoll_ctl2`].
+pub const SOCK_NOTIFY_TRIGGER_ONESHOT: c_int = 1 << 0;
+/// Trigger persistently, used by [`epoll_ctl2`].
+pub const SOCK_NOTIFY_TRIGGER_PERSISTENT: c_int = 1 << 1;
+/// Level trigger, used by [`epoll_ctl2`].
+pub const SOCK_NOTIFY_TRIGGER_LEVEL: c_int = 1 << 2;
+/// Edge trigger, used by [`epoll_ctl2`].
+pub const SOCK_NOTIFY_TRIGGER_EDGE: c_int = 1 << 3;
+
 /// Add an entry.
 pub const EPOLL_CTL_ADD: c_int = 1;
 /// Modify an entry.
@@ -63,13 +98,95 @@ pub const EPOLL_CTL_MOD: c_int = 2;
 /// Delete an entry.
 pub const EPOLL_CTL_DEL: c_int = 3;
 
-static POLLER_MAP: RwLock<HashMap<HANDLE_PTR, Poller>> = RwLock::new(HashMap::new());
+/// User data of [`epoll_event`].
+#[cfg(feature = "c-compat")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union epoll_data {
+    /// Pointer.
+    pub ptr: *mut c_void,
+    /// File descriptor.
+    pub fd: c_int,
+    /// 32-bit integer.
+    pub u32: u32,
+    /// 64-bit integer.
+    pub u64: u64,
+    /// Socket.
+    pub sock: SOCKET,
+    /// Handle.
+    pub hnd: HANDLE,
+}
+
+/// The event struct of epoll(2).
+///
+/// Only the lower bits of the user data that fit in a pointer are preserved,
+/// because they are used as the completion key.
+#[cfg(feature = "c-compat")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct epoll_event {
+    /// Epoll events and flags.
+    pub events: u32,
+    /// User data.
+    pub data: epoll_data,
+}
+
+#[cfg(feature = "c-compat")]
+impl epoll_event {
+    fn key(&self) -> usize {
+        unsafe { self.data.sock }
+    }
+
+    fn events(&self) -> u32 {
+        self.events
+    }
+}
+
+#[cfg(feature = "c-compat")]
+impl From<&Event> for epoll_event {
+    fn from(event: &Event) -> Self {
+        Self {
+            events: event.events(),
+            data: epoll_data {
+                u64: event.key() as u64,
+            },
+        }
+    }
+}
+
+/// The event struct of the FFI.
+///
+/// Without the `c-compat` feature, it is the same as [`Event`], so that the
+/// events are written to the buffer directly.
+#[cfg(not(feature = "c-compat"))]
+#[allow(non_camel_case_types)]
+pub type epoll_event = Event;
+
+/// Number of shards of the poller map.
+const POLLER_SHARDS: usize = 16;
+
+/// The pollers created by the FFI, sharded by their handles so that
+/// operations on different pollers rarely contend for the same lock.
+///
+/// The pollers are reference counted, so that the locks are not held while
+/// waiting.
+static POLLER_MAP: [RwLock<HashMap<HANDLE_PTR, Arc<Poller>>>; POLLER_SHARDS] =
+    [const { RwLock::new(HashMap::new()) }; POLLER_SHARDS];
+
+/// Gets the shard of the poller map holding the handle.
+fn poller_shard(poller: HANDLE) -> &'static RwLock<HashMap<HANDLE_PTR, Arc<Poller>>> {
+    // Handles are multiples of 4, the lower bits are always zero.
+    &POLLER_MAP[(poller as HANDLE_PTR >> 2) % POLLER_SHARDS]
+}
 
 #[inline(never)]
 fn epoll_try_create() -> Result<HANDLE> {
     let poller = Poller::new()?;
-    let handle = poller.port.as_raw_handle();
-    let mut map = POLLER_MAP.write();
+    let handle = poller.as_raw_handle();
+    let poller = Arc::try_new(poller).map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+    let mut map = poller_shard(handle).write();
     map.try_insert(handle as HANDLE_PTR, poller)
         .map_err(crate::map_try_reserve_error)?;
     Ok(handle)
@@ -79,65 +196,157 @@ fn epoll_try_create() -> Result<HANDLE> {
 #[unsafe(no_mangle)]
 #[deprecated]
 pub extern "C" fn epoll_create(size: c_int) -> HANDLE {
-    io_result_ret_handle(if size <= 0 {
-        Err(Error(ERROR_INVALID_PARAMETER))
+    let res = if size <= 0 {
+        Err(Error::new(ERROR_INVALID_PARAMETER))
     } else {
         epoll_try_create()
-    })
+    };
+    trace!("epoll_create({size}) = {res:?}");
+    io_result_ret_handle(res)
 }
 
 /// Create a new wepoll instance. `flags` should be zero.
 #[unsafe(no_mangle)]
 pub extern "C" fn epoll_create1(flags: c_int) -> HANDLE {
-    io_result_ret_handle({
-        if flags != 0 {
-            Err(Error(ERROR_INVALID_PARAMETER))
-        } else {
-            epoll_try_create()
-        }
-    })
+    let res = if flags != 0 {
+        Err(Error::new(ERROR_INVALID_PARAMETER))
+    } else {
+        epoll_try_create()
+    };
+    trace!("epoll_create1({flags}) = {res:?}");
+    io_result_ret_handle(res)
 }
 
 /// Close a wepoll instance.
+///
+/// The threads waiting on it are woken up, and their waits fail with
+/// `ERROR_INVALID_HANDLE`.
 #[unsafe(no_mangle)]
 pub extern "C" fn epoll_close(poller: HANDLE) -> c_int {
-    io_result_ret({
-        if let Some(poller) = POLLER_MAP.write().remove(&(poller as HANDLE_PTR)) {
+    let res = {
+        let removed = poller_shard(poller).write().remove(&(poller as HANDLE_PTR));
+        if let Some(poller) = removed {
+            // Every other reference is held by a waiting thread. The shutdown
+            // event is received by each of them, however many events they
+            // take, and the port is closed when the last one returns.
+            if Arc::strong_count(&poller) > 1 {
+                poller.shutdown().ok();
+            }
             drop(poller);
             Ok(0)
         } else {
-            Err(Error(ERROR_INVALID_PARAMETER))
+            Err(Error::new(ERROR_INVALID_PARAMETER))
         }
-    })
+    };
+    trace!("epoll_close({poller:?}) = {res:?}");
+    io_result_ret(res)
 }
 
 #[inline(never)]
 unsafe fn epoll_wait_duration(
     poller: HANDLE,
-    events: *mut Event,
+    events: *mut epoll_event,
     len: c_int,
     timeout: Option<Duration>,
     alertable: bool,
 ) -> c_int {
-    io_result_ret(
-        try {
-            let map = POLLER_MAP.read();
-            let poller = map
-                .get(&(poller as HANDLE_PTR))
-                .ok_or(Error(ERROR_INVALID_PARAMETER))?;
-            let len = len as usize;
-            let events = if len != 0 {
-                check_pointer(events)?;
-                unsafe { core::slice::from_raw_parts_mut(events.cast(), len) }
-            } else {
-                &mut []
-            };
-
-            let len = poller.wait(events, timeout, alertable)?;
-
-            len as _
-        },
-    )
+    let res: Result<c_int> = try {
+        let shard = poller_shard(poller);
+        let handle = poller as HANDLE_PTR;
+        // Don't hold the lock while waiting, so that the poller could be
+        // closed meanwhile.
+        let poller = shard
+            .read()
+            .get(&handle)
+            .cloned()
+            .ok_or(Error::new(ERROR_INVALID_PARAMETER))?;
+        let len = len as usize;
+        let events = if len != 0 {
+            check_pointer(events)?;
+            unsafe { core::slice::from_raw_parts_mut(events.cast(), len) }
+        } else {
+            &mut []
+        };
+
+        let len = epoll_wait_events(&poller, events, timeout, alertable)?;
+        let closed = shard
+            .read()
+            .get(&handle)
+            .is_none_or(|current| !Arc::ptr_eq(current, &poller));
+        if closed {
+            Err(Error::new(ERROR_INVALID_HANDLE))?;
+        }
+        len as _
+    };
+    trace!("epoll_wait({poller:?}, {len}, {timeout:?}, {alertable}) = {res:?}");
+    io_result_ret(res)
+}
+
+#[cfg(not(feature = "c-compat"))]
+fn epoll_wait_events(
+    poller: &Poller,
+    events: &mut [core::mem::MaybeUninit<epoll_event>],
+    timeout: Option<Duration>,
+    alertable: bool,
+) -> Result<usize> {
+    let received = poller.wait(events, timeout, alertable)?;
+    // Remove the wake-up events posted by `epoll_close`.
+    let mut len = 0;
+    for i in 0..received {
+        let mut event = unsafe { events[i].assume_init() };
+        if event.is_notify() {
+            continue;
+        }
+        if event.is_hangup() {
+            event.set_event(EPOLLRDHUP as _, true);
+        }
+        events[len].write(event);
+        len += 1;
+    }
+    Ok(len)
+}
+
+#[cfg(feature = "c-compat")]
+fn epoll_wait_events(
+    poller: &Poller,
+    events: &mut [MaybeUninit<epoll_event>],
+    timeout: Option<Duration>,
+    alertable: bool,
+) -> Result<usize> {
+    // `Event` is larger than `epoll_event`, so the events are received into a
+    // buffer on the stack and translated chunk by chunk. Only the first chunk
+    // waits; the following ones only collect the events already queued.
+    const CHUNK: usize = 64;
+
+    let mut buffer = [const { MaybeUninit::<Event>::uninit() }; CHUNK];
+    let mut timeout = timeout;
+    let mut total = 0;
+    loop {
+        let chunk = (events.len() - total).min(CHUNK);
+        let len = poller.wait(&mut buffer[..chunk], timeout, alertable)?;
+        // The shutdown event is posted back by every wait, stop here.
+        let shutdown = buffer[..len]
+            .iter()
+            .any(|event| unsafe { event.assume_init_ref() }.is_shutdown());
+        let received = buffer[..len]
+            .iter()
+            .map(|event| unsafe { event.assume_init_ref() })
+            // Remove the wake-up events posted by `epoll_close`.
+            .filter(|event| !event.is_notify());
+        for (dst, src) in events[total..].iter_mut().zip(received) {
+            let mut event = epoll_event::from(src);
+            if (event.events & Interest::HANGUP.bits()) != 0 {
+                event.events |= EPOLLRDHUP as u32;
+            }
+            dst.write(event);
+            total += 1;
+        }
+        if shutdown || len < chunk || total == events.len() {
+            break;
+        }
+        timeout = Some(Duration::ZERO);
+    }
+    Ok(total)
 }
 
 /// Wait for events on the wepoll instance.
@@ -148,7 +357,7 @@ unsafe fn epoll_wait_duration(
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn epoll_wait(
     poller: HANDLE,
-    events: *mut Event,
+    events: *mut epoll_event,
     len: c_int,
     timeout: c_int,
 ) -> c_int {
@@ -166,7 +375,7 @@ pub unsafe extern "C" fn epoll_wait(
 #[inline(never)]
 pub unsafe extern "C" fn epoll_pwait(
     poller: HANDLE,
-    events: *mut Event,
+    events: *mut epoll_event,
     len: c_int,
     timeout: c_int,
     alertable: bool,
@@ -189,7 +398,7 @@ pub unsafe extern "C" fn epoll_pwait(
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn epoll_pwait2(
     poller: HANDLE,
-    events: *mut Event,
+    events: *mut epoll_event,
     len: c_int,
     timeout: *const libc::timespec,
     alertable: bool,
@@ -198,64 +407,151 @@ pub unsafe extern "C" fn epoll_pwait2(
         unsafe { epoll_wait_duration(poller, events, len, None, alertable) }
     } else if timeout.is_aligned() {
         let timeout = unsafe { &*timeout };
+        // Clamp rather than overflow to an infinite wait.
         let timeout = Duration::from_nanos(timeout.tv_nsec as _)
-            .checked_add(Duration::from_secs(timeout.tv_sec as _));
-        unsafe { epoll_wait_duration(poller, events, len, timeout, alertable) }
+            .checked_add(Duration::from_secs(timeout.tv_sec as _))
+            .unwrap_or(Duration::MAX);
+        unsafe { epoll_wait_duration(poller, events, len, Some(timeout), alertable) }
     } else {
         unsafe { SetLastError(ERROR_INVALID_PARAMETER) };
         -1
     }
 }
 
+fn check_handle(handle: HANDLE) -> Result<HANDLE> {
+    if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+        Err(Error::new(ERROR_INVALID_HANDLE))
+    } else {
+        Ok(handle)
+    }
+}
+
 fn is_socket(handle: HANDLE) -> bool {
-    let res = unsafe { WSAGetQOSByName(handle as _, null(), null_mut()) };
-    res != 0 || (unsafe { WSAGetLastError() } != WSAENOTSOCK)
+    check_handle(handle).is_ok() && crate::is_socket(handle as SOCKET)
 }
 
-fn interest_mode(event: *const Event) -> Result<(Event, PollMode)> {
+/// Classifies the handle for the operation.
+///
+/// The registered handles are classified by how they were added, to avoid
+/// probing them on every `EPOLL_CTL_MOD` and `EPOLL_CTL_DEL`.
+fn is_socket_of(registry: &Registry, op: c_int, handle: HANDLE) -> bool {
+    if op != EPOLL_CTL_ADD {
+        if registry.sources.contains_key(&(handle as SOCKET)) {
+            return true;
+        }
+        if registry.waitables.contains_key(&handle) {
+            return false;
+        }
+    }
+    is_socket(handle)
+}
+
+fn trigger_mode(flags: c_int) -> Result<PollMode> {
+    const ONESHOT_LEVEL: c_int = SOCK_NOTIFY_TRIGGER_ONESHOT | SOCK_NOTIFY_TRIGGER_LEVEL;
+    const PERSISTENT_LEVEL: c_int = SOCK_NOTIFY_TRIGGER_PERSISTENT | SOCK_NOTIFY_TRIGGER_LEVEL;
+    const PERSISTENT_EDGE: c_int = SOCK_NOTIFY_TRIGGER_PERSISTENT | SOCK_NOTIFY_TRIGGER_EDGE;
+    const ONESHOT_EDGE: c_int = SOCK_NOTIFY_TRIGGER_ONESHOT | SOCK_NOTIFY_TRIGGER_EDGE;
+
+    match flags {
+        ONESHOT_LEVEL => Ok(PollMode::Oneshot),
+        PERSISTENT_LEVEL => Ok(PollMode::Level),
+        PERSISTENT_EDGE => Ok(PollMode::Edge),
+        ONESHOT_EDGE => Ok(PollMode::EdgeOneshot),
+        _ => Err(Error::new(ERROR_INVALID_PARAMETER)),
+    }
+}
+
+fn interest_mode(
+    event: *const epoll_event,
+    trigger: Option<PollMode>,
+) -> Result<(usize, Interest, PollMode)> {
     let event = check_pointer(event)?;
     let events = event.events() as c_int;
-    let mode = match (((events & EPOLLET) != 0), ((events & EPOLLONESHOT) != 0)) {
-        (false, false) => PollMode::Level,
-        (false, true) => PollMode::Oneshot,
-        (true, false) => PollMode::Edge,
-        (true, true) => PollMode::EdgeOneshot,
+    let mode = match trigger {
+        Some(mode) => mode,
+        None => match (((events & EPOLLET) != 0), ((events & EPOLLONESHOT) != 0)) {
+            (false, false) => PollMode::Level,
+            (false, true) => PollMode::Oneshot,
+            (true, false) => PollMode::Edge,
+            (true, true) => PollMode::EdgeOneshot,
+        },
     };
-    Ok((*event, mode))
+    let mut interest = Interest::from_bits_truncate(event.events());
+    if (events & EPOLLRDHUP) != 0 {
+        interest |= Interest::HANGUP;
+    }
+    Ok((event.key(), interest, mode))
 }
 
 fn epoll_ctl_socket(
-    poller: &mut Poller,
+    poller: &Poller,
     op: c_int,
     socket: SOCKET,
-    event: *const Event,
+    event: *const epoll_event,
+    trigger: Option<PollMode>,
 ) -> Result<()> {
     match op {
         EPOLL_CTL_ADD => {
-            let (interest, mode) = interest_mode(event)?;
-            poller.add(socket, interest, mode)?
+            let (key, interest, mode) = interest_mode(event, trigger)?;
+            poller.add(socket, key, interest, mode)?
         }
         EPOLL_CTL_MOD => {
-            let (interest, mode) = interest_mode(event)?;
-            poller.modify(socket, interest, mode)?
+            let (key, interest, mode) = interest_mode(event, trigger)?;
+            poller.modify(socket, key, interest, mode)?
         }
         EPOLL_CTL_DEL => poller.delete(socket)?,
-        _ => return Err(Error(ERROR_INVALID_PARAMETER)),
+        _ => return Err(Error::new(ERROR_INVALID_PARAMETER)),
     }
     Ok(())
 }
 
+/// Like [`epoll_ctl_socket`] with the registry locked. The operations
+/// waiting for the removal of the registration fail with `ERROR_BUSY`.
+fn epoll_ctl_socket_locked(
+    poller: &Poller,
+    registry: &mut Registry,
+    op: c_int,
+    socket: SOCKET,
+    event: *const epoll_event,
+    trigger: Option<PollMode>,
+) -> Result<()> {
+    match op {
+        EPOLL_CTL_ADD => {
+            let (key, interest, mode) = interest_mode(event, trigger)?;
+            check_key(key)?;
+            poller.add_locked(registry, socket, key, interest, mode)?
+        }
+        EPOLL_CTL_MOD => {
+            let (key, interest, mode) = interest_mode(event, trigger)?;
+            poller.modify_locked(registry, socket, key, interest, mode)?
+        }
+        EPOLL_CTL_DEL => return Err(Error::new(ERROR_BUSY)),
+        _ => return Err(Error::new(ERROR_INVALID_PARAMETER)),
+    }
+    Ok(())
+}
+
+/// The operations on waitables don't wait, and are done with the registry
+/// locked by the caller.
 fn epoll_ctl_waitable(
-    poller: &mut Poller,
+    poller: &Poller,
+    registry: &mut Registry,
     op: c_int,
     handle: HANDLE,
-    event: *const Event,
+    event: *const epoll_event,
+    trigger: Option<PollMode>,
 ) -> Result<()> {
     match op {
-        EPOLL_CTL_ADD => poller.add_waitable(handle, *check_pointer(event)?)?,
-        EPOLL_CTL_MOD => poller.modify_waitable(handle, *check_pointer(event)?)?,
-        EPOLL_CTL_DEL => poller.delete_waitable(handle)?,
-        _ => return Err(Error(ERROR_INVALID_PARAMETER)),
+        EPOLL_CTL_ADD => {
+            let (key, interest, mode) = interest_mode(event, trigger)?;
+            poller.add_waitable_locked(registry, handle, key, interest, mode, STATUS_SUCCESS)?
+        }
+        EPOLL_CTL_MOD => {
+            let (_, interest, mode) = interest_mode(event, trigger)?;
+            poller.modify_waitable_locked(registry, handle, interest, mode)?
+        }
+        EPOLL_CTL_DEL => poller.delete_waitable_locked(registry, handle)?,
+        _ => return Err(Error::new(ERROR_INVALID_PARAMETER)),
     }
     Ok(())
 }
@@ -270,28 +566,164 @@ pub unsafe extern "C" fn epoll_ctl(
     poller: HANDLE,
     op: c_int,
     handle: HANDLE,
-    event: *mut Event,
+    event: *mut epoll_event,
 ) -> c_int {
-    io_result_ret(
-        try {
-            let mut map = POLLER_MAP.write();
-            let poller = map
-                .get_mut(&(poller as HANDLE_PTR))
-                .ok_or(Error(ERROR_INVALID_PARAMETER))?;
-            if is_socket(handle) {
-                epoll_ctl_socket(poller, op, handle as _, event)?;
-            } else {
-                epoll_ctl_waitable(poller, op, handle, event)?;
-            }
-            0
-        },
-    )
+    let res = epoll_ctl_trigger(poller, op, handle, event, None, false);
+    trace!(
+        "epoll_ctl({poller:?}, {op}, {handle:?}, {:?}) = {res:?}",
+        trace_events(event)
+    );
+    io_result_ret(res)
+}
+
+/// Add, modify, or remove entries in the wepoll interest list, without
+/// blocking on the locks.
+///
+/// It fails with `ERROR_BUSY` if the wepoll instance is locked by another
+/// thread, e.g., another `epoll_ctl`, and the caller could defer the
+/// operation. The operations waiting for the removal of a socket, i.e.,
+/// `EPOLL_CTL_DEL` of a socket and `EPOLL_CTL_MOD` changing its data, also
+/// fail with `ERROR_BUSY`, and should be done by `epoll_ctl` instead.
+///
+/// # Safety
+///
+/// Given pointer should be valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn epoll_ctl_try(
+    poller: HANDLE,
+    op: c_int,
+    handle: HANDLE,
+    event: *mut epoll_event,
+) -> c_int {
+    let res = epoll_ctl_trigger(poller, op, handle, event, None, true);
+    trace!(
+        "epoll_ctl_try({poller:?}, {op}, {handle:?}, {:?}) = {res:?}",
+        trace_events(event)
+    );
+    io_result_ret(res)
+}
+
+/// Add, modify, or remove entries in the wepoll interest list, with the
+/// trigger flags of `ProcessSocketNotifications`.
+///
+/// `trigger_flags` should be `SOCK_NOTIFY_TRIGGER_ONESHOT` or
+/// `SOCK_NOTIFY_TRIGGER_PERSISTENT`, combined with `SOCK_NOTIFY_TRIGGER_LEVEL`
+/// or `SOCK_NOTIFY_TRIGGER_EDGE`. `EPOLLET` and `EPOLLONESHOT` in the event
+/// are ignored. Waitables only accept `SOCK_NOTIFY_TRIGGER_ONESHOT` or
+/// `SOCK_NOTIFY_TRIGGER_PERSISTENT` combined with `SOCK_NOTIFY_TRIGGER_LEVEL`,
+/// and fail with `ERROR_NOT_SUPPORTED` otherwise.
+///
+/// # Safety
+///
+/// Given pointer should be valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn epoll_ctl2(
+    poller: HANDLE,
+    op: c_int,
+    handle: HANDLE,
+    event: *mut epoll_event,
+    trigger_flags: c_int,
+) -> c_int {
+    let res = trigger_mode(trigger_flags)
+        .and_then(|mode| epoll_ctl_trigger(poller, op, handle, event, Some(mode), false));
+    trace!(
+        "epoll_ctl2({poller:?}, {op}, {handle:?}, {:?}, {trigger_flags}) = {res:?}",
+        trace_events(event)
+    );
+    io_result_ret(res)
+}
+
+fn epoll_ctl_trigger(
+    poller: HANDLE,
+    op: c_int,
+    handle: HANDLE,
+    event: *mut epoll_event,
+    trigger: Option<PollMode>,
+    nonblocking: bool,
+) -> Result<c_int> {
+    let shard = poller_shard(poller);
+    let map = if nonblocking {
+        shard.try_read().ok_or(Error::new(ERROR_BUSY))?
+    } else {
+        shard.read()
+    };
+    let poller = map
+        .get(&(poller as HANDLE_PTR))
+        .ok_or(Error::new(ERROR_INVALID_PARAMETER))?;
+    let handle = check_handle(handle)?;
+    if nonblocking {
+        // The operations are done with the guard, and not locked again.
+        let mut registry = poller.registry.try_write().ok_or(Error::new(ERROR_BUSY))?;
+        if is_socket_of(&registry, op, handle) {
+            epoll_ctl_socket_locked(poller, &mut registry, op, handle as _, event, trigger)?;
+        } else {
+            epoll_ctl_waitable(poller, &mut registry, op, handle, event, trigger)?;
+        }
+    } else if is_socket_of(&poller.registry.read(), op, handle) {
+        epoll_ctl_socket(poller, op, handle as _, event, trigger)?;
+    } else {
+        epoll_ctl_waitable(
+            poller,
+            &mut poller.registry.write(),
+            op,
+            handle,
+            event,
+            trigger,
+        )?;
+    }
+    Ok(0)
+}
+
+/// Post a user event to the wepoll instance.
+///
+/// The event is received by `epoll_wait` with the same data, and the
+/// readable, writable, hangup and error flags.
+///
+/// # Safety
+///
+/// Given pointer should be valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn epoll_post(poller: HANDLE, event: *const epoll_event) -> c_int {
+    let res: Result<c_int> = try {
+        let map = poller_shard(poller).read();
+        let poller = map
+            .get(&(poller as HANDLE_PTR))
+            .ok_or(Error::new(ERROR_INVALID_PARAMETER))?;
+        let event = check_pointer(event)?;
+        let mut posted = Event::none(event.key());
+        posted.set_event(event.events(), true);
+        poller.post(posted)?;
+        0
+    };
+    trace!(
+        "epoll_post({poller:?}, {:?}) = {res:?}",
+        trace_events(event)
+    );
+    io_result_ret(res)
+}
+
+/// Check whether events are immediately available on the wepoll instance.
+///
+/// Returns 1 if any, 0 if none, or -1 on error. The queue is probed without
+/// waiting, and the probed event is queued again, after the others.
+#[unsafe(no_mangle)]
+pub extern "C" fn epoll_pending(poller: HANDLE) -> c_int {
+    let res: Result<c_int> = try {
+        let map = poller_shard(poller).read();
+        let poller = map
+            .get(&(poller as HANDLE_PTR))
+            .ok_or(Error::new(ERROR_INVALID_PARAMETER))?;
+        poller.has_pending()? as _
+    };
+    trace!("epoll_pending({poller:?}) = {res:?}");
+    io_result_ret(res)
 }
 
 #[cfg(all(test, feature = "std"))]
 mod test {
     use std::{
         fs::File,
+        mem::MaybeUninit,
         os::windows::io::{AsRawHandle, AsRawSocket, FromRawHandle, OwnedHandle},
         ptr::null,
     };
@@ -313,24 +745,179 @@ mod test {
         assert!(!e.is_null());
         let e = unsafe { OwnedHandle::from_raw_handle(e) };
         assert!(!is_socket(e.as_raw_handle()));
+
+        assert!(!is_socket(null_mut()));
+        assert!(!is_socket(INVALID_HANDLE_VALUE));
     }
 
     #[test]
     fn create() {
         let h = epoll_create1(0);
         assert!(!h.is_null());
-        assert!(!POLLER_MAP.read().is_empty());
+        assert!(poller_shard(h).read().contains_key(&(h as HANDLE_PTR)));
         let res = epoll_close(h);
         assert_eq!(res, 0);
-        assert!(POLLER_MAP.read().is_empty());
+        assert!(!poller_shard(h).read().contains_key(&(h as HANDLE_PTR)));
+    }
+
+    #[test]
+    fn trigger() {
+        assert_eq!(
+            trigger_mode(SOCK_NOTIFY_TRIGGER_PERSISTENT | SOCK_NOTIFY_TRIGGER_LEVEL).unwrap(),
+            PollMode::Level
+        );
+        assert_eq!(
+            trigger_mode(SOCK_NOTIFY_TRIGGER_ONESHOT | SOCK_NOTIFY_TRIGGER_EDGE).unwrap(),
+            PollMode::EdgeOneshot
+        );
+        assert!(trigger_mode(SOCK_NOTIFY_TRIGGER_LEVEL).is_err());
+        assert!(trigger_mode(SOCK_NOTIFY_TRIGGER_LEVEL | SOCK_NOTIFY_TRIGGER_EDGE).is_err());
     }
 
     #[test]
     fn wait() {
         let h = epoll_create1(0);
         assert!(!h.is_null());
-        let mut event = Event::none(0);
-        let res = unsafe { epoll_wait(h, &mut event, 1, 100) };
+        let mut event = MaybeUninit::<epoll_event>::uninit();
+        let res = unsafe { epoll_wait(h, event.as_mut_ptr(), 1, 100) };
+        assert_eq!(res, 0);
+        let res = epoll_close(h);
+        assert_eq!(res, 0);
+    }
+
+    #[test]
+    fn close_while_waiting() {
+        let h = epoll_create1(0);
+        assert!(!h.is_null());
+        let handle = h as usize;
+        let waiter = std::thread::spawn(move || {
+            let mut event = MaybeUninit::<epoll_event>::uninit();
+            let res = unsafe { epoll_wait(handle as HANDLE, event.as_mut_ptr(), 1, -1) };
+            (res, std::io::Error::last_os_error().raw_os_error())
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let res = epoll_close(h);
+        assert_eq!(res, 0);
+        let (res, error) = waiter.join().unwrap();
+        assert_eq!(res, -1);
+        assert_eq!(error, Some(ERROR_INVALID_HANDLE as _));
+    }
+
+    #[test]
+    fn close_while_waiting_many() {
+        let h = epoll_create1(0);
+        assert!(!h.is_null());
+        let handle = h as usize;
+        let waiters = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    let mut events = [MaybeUninit::<epoll_event>::uninit(); 8];
+                    let res =
+                        unsafe { epoll_wait(handle as HANDLE, events.as_mut_ptr().cast(), 8, -1) };
+                    (res, std::io::Error::last_os_error().raw_os_error())
+                })
+            })
+            .collect::<Vec<_>>();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let res = epoll_close(h);
+        assert_eq!(res, 0);
+        for waiter in waiters {
+            let (res, error) = waiter.join().unwrap();
+            assert_eq!(res, -1);
+            assert_eq!(error, Some(ERROR_INVALID_HANDLE as _));
+        }
+    }
+
+    #[test]
+    fn post() {
+        let h = epoll_create1(0);
+        assert!(!h.is_null());
+        let posted = Event::readable(1);
+        #[cfg(feature = "c-compat")]
+        let posted = epoll_event::from(&posted);
+        let res = unsafe { epoll_post(h, &posted) };
+        assert_eq!(res, 0);
+        let mut event = MaybeUninit::<epoll_event>::uninit();
+        let res = unsafe { epoll_wait(h, event.as_mut_ptr(), 1, 100) };
+        assert_eq!(res, 1);
+        let event = unsafe { event.assume_init() };
+        assert_eq!(event.key(), 1);
+        assert_eq!(event.events() as c_int, EPOLLIN);
+        let res = epoll_close(h);
+        assert_eq!(res, 0);
+    }
+
+    #[test]
+    fn pending() {
+        let h = epoll_create1(0);
+        assert!(!h.is_null());
+        assert_eq!(epoll_pending(h), 0);
+        let posted = Event::readable(1);
+        #[cfg(feature = "c-compat")]
+        let posted = epoll_event::from(&posted);
+        let res = unsafe { epoll_post(h, &posted) };
+        assert_eq!(res, 0);
+        assert_eq!(epoll_pending(h), 1);
+        let mut event = MaybeUninit::<epoll_event>::uninit();
+        let res = unsafe { epoll_wait(h, event.as_mut_ptr(), 1, 0) };
+        assert_eq!(res, 1);
+        assert_eq!(epoll_pending(h), 0);
+        let res = epoll_close(h);
+        assert_eq!(res, 0);
+    }
+
+    #[test]
+    fn ctl_try() {
+        let h = epoll_create1(0);
+        assert!(!h.is_null());
+        let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+        let socket = s.as_raw_socket() as HANDLE;
+        let event = Event::readable(1);
+        #[cfg(feature = "c-compat")]
+        let event = epoll_event::from(&event);
+        let mut event = event;
+        {
+            let map = poller_shard(h).read();
+            let poller = map.get(&(h as HANDLE_PTR)).unwrap();
+            let _registry = poller.registry.write();
+            let res = unsafe { epoll_ctl_try(h, EPOLL_CTL_ADD, socket, &mut event) };
+            assert_eq!(res, -1);
+            let error = std::io::Error::last_os_error().raw_os_error();
+            assert_eq!(error, Some(ERROR_BUSY as _));
+        }
+        let res = unsafe { epoll_ctl_try(h, EPOLL_CTL_ADD, socket, &mut event) };
+        assert_eq!(res, 0);
+        let res = unsafe { epoll_ctl_try(h, EPOLL_CTL_MOD, socket, &mut event) };
+        assert_eq!(res, 0);
+
+        // The removal of the registration would be waited for.
+        let res = unsafe { epoll_ctl_try(h, EPOLL_CTL_DEL, socket, null_mut()) };
+        assert_eq!(res, -1);
+        let error = std::io::Error::last_os_error().raw_os_error();
+        assert_eq!(error, Some(ERROR_BUSY as _));
+        let res = unsafe { epoll_ctl(h, EPOLL_CTL_DEL, socket, null_mut()) };
+        assert_eq!(res, 0);
+
+        let res = epoll_close(h);
+        assert_eq!(res, 0);
+    }
+
+    #[test]
+    fn delete_closed() {
+        let h = epoll_create1(0);
+        assert!(!h.is_null());
+        let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+        let socket = s.as_raw_socket() as HANDLE;
+        let event = Event::readable(1);
+        #[cfg(feature = "c-compat")]
+        let event = epoll_event::from(&event);
+        let mut event = event;
+        let res = unsafe { epoll_ctl(h, EPOLL_CTL_ADD, socket, &mut event) };
+        assert_eq!(res, 0);
+
+        // The closed socket is still deleted as a socket.
+        drop(s);
+        let res = unsafe { epoll_ctl(h, EPOLL_CTL_DEL, socket, null_mut()) };
         assert_eq!(res, 0);
         let res = epoll_close(h);
         assert_eq!(res, 0);
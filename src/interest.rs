@@ -0,0 +1,171 @@
+//! Interest of the registered sockets and waitables.
+
+use core::{
+    fmt::{self, Debug, Display},
+    ops::{BitAnd, BitOr, BitOrAssign},
+};
+
+use windows_sys::Win32::Networking::WinSock::{
+    SOCK_NOTIFY_EVENT_ERR, SOCK_NOTIFY_EVENT_HANGUP, SOCK_NOTIFY_EVENT_IN, SOCK_NOTIFY_EVENT_OUT,
+};
+
+use crate::Event;
+
+/// Interest in the events of a socket or a waitable.
+///
+/// It is the input of [`Poller::add`] and its friends, while [`Event`] is the
+/// output of [`Poller::wait`].
+///
+/// [`Poller::add`]: crate::Poller::add
+/// [`Poller::wait`]: crate::Poller::wait
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(transparent)]
+pub struct Interest(u32);
+
+/// The readiness of a received event, see [`Event::readiness`].
+///
+/// It shares the flags with [`Interest`], so that the readiness could be
+/// masked by the registered interest directly.
+pub type Readiness = Interest;
+
+/// The single flags and their names, in the order of iteration.
+const FLAGS: [(Interest, &str); 4] = [
+    (Interest::READABLE, "READABLE"),
+    (Interest::WRITABLE, "WRITABLE"),
+    (Interest::HANGUP, "HANGUP"),
+    (Interest::ERROR, "ERROR"),
+];
+
+impl Interest {
+    /// Interest in all events.
+    pub const ALL: Self = Self(
+        SOCK_NOTIFY_EVENT_IN
+            | SOCK_NOTIFY_EVENT_OUT
+            | SOCK_NOTIFY_EVENT_HANGUP
+            | SOCK_NOTIFY_EVENT_ERR,
+    );
+    /// Interest in error event.
+    pub const ERROR: Self = Self(SOCK_NOTIFY_EVENT_ERR);
+    /// Interest in hangup event.
+    pub const HANGUP: Self = Self(SOCK_NOTIFY_EVENT_HANGUP);
+    /// No interest.
+    pub const NONE: Self = Self(0);
+    /// Interest in readable event.
+    pub const READABLE: Self = Self(SOCK_NOTIFY_EVENT_IN);
+    /// Interest in writable event.
+    pub const WRITABLE: Self = Self(SOCK_NOTIFY_EVENT_OUT);
+
+    /// Create an interest from the event flags. Unknown flags are dropped.
+    pub const fn from_bits_truncate(bits: u32) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    /// The event flags of the interest.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether there is no interest.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all interests in `other` are contained.
+    pub const fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+
+    /// Add the interests in `other`.
+    pub const fn add(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Remove the interests in `other`.
+    pub const fn remove(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Interest in readable event.
+    pub const fn is_readable(self) -> bool {
+        self.contains(Self::READABLE)
+    }
+
+    /// Interest in writable event.
+    pub const fn is_writable(self) -> bool {
+        self.contains(Self::WRITABLE)
+    }
+
+    /// Interest in hangup event.
+    pub const fn is_hangup(self) -> bool {
+        self.contains(Self::HANGUP)
+    }
+
+    /// Interest in error event.
+    pub const fn is_error(self) -> bool {
+        self.contains(Self::ERROR)
+    }
+
+    /// Iterates the single flags contained, in the order of readable,
+    /// writable, hangup and error.
+    pub fn iter(self) -> impl Iterator<Item = Self> {
+        FLAGS
+            .into_iter()
+            .map(|(flag, _)| flag)
+            .filter(move |flag| self.contains(*flag))
+    }
+
+    /// Create an event with the key and the interest as flags.
+    pub fn to_event(self, key: usize) -> Event {
+        let mut event = Event::none(key);
+        event.0.dwNumberOfBytesTransferred = self.0;
+        event
+    }
+}
+
+impl BitOr for Interest {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.add(rhs)
+    }
+}
+
+impl BitOrAssign for Interest {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = self.add(rhs);
+    }
+}
+
+impl BitAnd for Interest {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Debug for Interest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Shows the flags joined by `|`, e.g., `READABLE | HANGUP`, or `NONE`.
+impl Display for Interest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("NONE");
+        }
+        let mut first = true;
+        for (interest, name) in FLAGS {
+            if self.contains(interest) {
+                if !first {
+                    f.write_str(" | ")?;
+                }
+                f.write_str(name)?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,21 @@
+#[cfg(feature = "provide-allocator")]
+use core::alloc::{GlobalAlloc, Layout};
 use core::{
-    alloc::{GlobalAlloc, Layout},
-    fmt::Debug,
+    fmt::{Debug, Display},
+    ptr::null_mut,
 };
 
-use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, HANDLE, WIN32_ERROR};
+#[cfg(feature = "std")]
+use windows_sys::Win32::Networking::WinSock::SOCKET;
+use windows_sys::Win32::{
+    Foundation::{
+        CloseHandle, DUPLICATE_SAME_ACCESS, DuplicateHandle, ERROR_ABANDONED_WAIT_0,
+        ERROR_NO_SYSTEM_RESOURCES, ERROR_NOT_ENOUGH_MEMORY, ERROR_NOT_ENOUGH_QUOTA,
+        ERROR_NOT_FOUND, GetLastError, HANDLE, WIN32_ERROR,
+    },
+    Networking::WinSock::{WSAENOBUFS, WSAENOTSOCK},
+    System::Threading::GetCurrentProcess,
+};
 
 #[derive(Debug)]
 pub struct OwnedHandle(HANDLE);
@@ -16,6 +28,33 @@ impl OwnedHandle {
     pub fn as_raw_handle(&self) -> HANDLE {
         self.0
     }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        Self::duplicate(self.0)
+    }
+
+    /// Duplicate a handle, including pseudo handles like `GetCurrentThread`.
+    pub fn duplicate(handle: HANDLE) -> Result<Self> {
+        let process = unsafe { GetCurrentProcess() };
+        let source = handle;
+        let mut handle = null_mut();
+        let res = unsafe {
+            DuplicateHandle(
+                process,
+                source,
+                process,
+                &mut handle,
+                0,
+                0,
+                DUPLICATE_SAME_ACCESS,
+            )
+        };
+        if res == 0 {
+            Err(Error::last_os_error().with_context("DuplicateHandle"))
+        } else {
+            Ok(Self(handle))
+        }
+    }
 }
 
 impl Drop for OwnedHandle {
@@ -28,18 +67,173 @@ unsafe impl Send for OwnedHandle {}
 unsafe impl Sync for OwnedHandle {}
 
 /// Win32 error with error code.
-pub struct Error(pub WIN32_ERROR);
+///
+/// It may carry the operation where it originates, see [`Error::context`].
+pub struct Error(pub WIN32_ERROR, Option<&'static str>);
 
 impl Error {
+    /// Create [`Error`] from the error code.
+    pub const fn new(code: WIN32_ERROR) -> Self {
+        Self(code, None)
+    }
+
     /// Create [`Error`] from [`GetLastError`].
     pub fn last_os_error() -> Self {
-        Self(unsafe { GetLastError() })
+        Self::new(unsafe { GetLastError() })
+    }
+
+    /// Attaches the operation where the error originates.
+    pub const fn with_context(self, context: &'static str) -> Self {
+        Self(self.0, Some(context))
+    }
+
+    /// The operation where the error originates, e.g., the name of the
+    /// system call, if known.
+    pub const fn context(&self) -> Option<&'static str> {
+        self.1
+    }
+
+    /// Whether the wait was abandoned because the port of the poller was
+    /// closed. See [`Poller::wait`].
+    ///
+    /// [`Poller::wait`]: crate::Poller::wait
+    pub fn is_abandoned(&self) -> bool {
+        self.0 == ERROR_ABANDONED_WAIT_0
+    }
+
+    /// Whether the system, the process or the poller is out of resources for
+    /// more registrations, see [`RegistrationError::Quota`].
+    ///
+    /// It includes the kernel limits hit by `ProcessSocketNotifications`, a
+    /// full poller created by [`Poller::with_capacity`], and the failure to
+    /// allocate the bookkeeping of a registration, `ERROR_NOT_ENOUGH_MEMORY`.
+    /// A server could stop accepting new connections until some are closed,
+    /// instead of retrying immediately.
+    ///
+    /// [`Poller::with_capacity`]: crate::Poller::with_capacity
+    pub fn is_quota_exhausted(&self) -> bool {
+        self.registration_error() == RegistrationError::Quota
+    }
+
+    /// Classifies the error of a socket operation, e.g., [`Poller::add`].
+    ///
+    /// [`Poller::add`]: crate::Poller::add
+    pub fn registration_error(&self) -> RegistrationError {
+        RegistrationError::from_code(self.0)
+    }
+}
+
+/// The common failures when registering a socket.
+///
+/// The `registrationResult` of `ProcessSocketNotifications` is a Win32 error
+/// code, which may also be a WinSock one. See [`Error::registration_error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RegistrationError {
+    /// The socket is not registered, or has been removed. `ERROR_NOT_FOUND`.
+    NotFound,
+    /// The handle is not a socket. `WSAENOTSOCK`.
+    NotSocket,
+    /// The system or the process is out of resources, i.e.,
+    /// `ERROR_NO_SYSTEM_RESOURCES`, `ERROR_NOT_ENOUGH_QUOTA`,
+    /// `ERROR_NOT_ENOUGH_MEMORY` or `WSAENOBUFS`.
+    ///
+    /// `ERROR_NOT_ENOUGH_MEMORY` is also returned when the heap of the process
+    /// is exhausted. It is counted as well, because the registrations hold
+    /// memory, and closing some of them frees it, the same as the other
+    /// limits.
+    Quota,
+    /// Other error codes.
+    Other(WIN32_ERROR),
+}
+
+impl RegistrationError {
+    /// Maps the error code.
+    pub const fn from_code(code: WIN32_ERROR) -> Self {
+        match code {
+            ERROR_NOT_FOUND => Self::NotFound,
+            c if c == WSAENOTSOCK as WIN32_ERROR => Self::NotSocket,
+            ERROR_NO_SYSTEM_RESOURCES | ERROR_NOT_ENOUGH_QUOTA | ERROR_NOT_ENOUGH_MEMORY => {
+                Self::Quota
+            }
+            c if c == WSAENOBUFS as WIN32_ERROR => Self::Quota,
+            c => Self::Other(c),
+        }
+    }
+
+    /// The error code. For the variants other than
+    /// [`RegistrationError::Other`], it is the representative code.
+    pub const fn code(&self) -> WIN32_ERROR {
+        match self {
+            Self::NotFound => ERROR_NOT_FOUND,
+            Self::NotSocket => WSAENOTSOCK as _,
+            Self::Quota => ERROR_NO_SYSTEM_RESOURCES,
+            Self::Other(c) => *c,
+        }
     }
 }
 
 impl Debug for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::result::Result<(), core::fmt::Error> {
-        errno::Errno(self.0 as _).fmt(f)
+        if let Some(context) = self.1 {
+            write!(f, "{context}: ")?;
+        }
+        Debug::fmt(&errno::Errno(self.0 as _), f)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::result::Result<(), core::fmt::Error> {
+        if let Some(context) = self.1 {
+            write!(f, "{context}: ")?;
+        }
+        // `errno` formats the message with `FormatMessageW`.
+        Display::fmt(&errno::Errno(self.0 as _), f)?;
+        write!(f, " (os error {})", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        Self::from_raw_os_error(e.0 as _)
+    }
+}
+
+#[cfg(feature = "std")]
+impl TryFrom<std::io::Error> for Error {
+    type Error = std::io::Error;
+
+    /// Extracts the raw OS error, or gives back the original error if there
+    /// isn't one.
+    fn try_from(e: std::io::Error) -> core::result::Result<Self, Self::Error> {
+        match e.raw_os_error() {
+            Some(code) => Ok(Self::new(code as _)),
+            None => Err(e),
+        }
+    }
+}
+
+/// A socket which could be registered with a [`Poller`].
+///
+/// It is implemented for all [`AsRawSocket`] types, so that the raw socket is
+/// converted to `SOCKET` in one place.
+///
+/// [`Poller`]: crate::Poller
+/// [`AsRawSocket`]: std::os::windows::io::AsRawSocket
+#[cfg(feature = "std")]
+pub trait AsPollSocket {
+    /// The raw socket to register.
+    fn as_poll_socket(&self) -> SOCKET;
+}
+
+#[cfg(feature = "std")]
+impl<T: std::os::windows::io::AsRawSocket + ?Sized> AsPollSocket for T {
+    fn as_poll_socket(&self) -> SOCKET {
+        self.as_raw_socket() as _
     }
 }
 
@@ -47,13 +241,15 @@ impl Debug for Error {
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[panic_handler]
-#[cfg(not(feature = "std"))]
+#[cfg(all(feature = "provide-allocator", not(feature = "std")))]
 fn panic(_: &core::panic::PanicInfo) -> ! {
     unsafe { libc::abort() }
 }
 
+#[cfg(feature = "provide-allocator")]
 struct LibcAllocator;
 
+#[cfg(feature = "provide-allocator")]
 unsafe impl GlobalAlloc for LibcAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         unsafe { libc::aligned_malloc(layout.size(), layout.align()).cast() }
@@ -64,9 +260,44 @@ unsafe impl GlobalAlloc for LibcAllocator {
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // `_aligned_realloc` requires the alignment of the original block,
+        // which is the one of `layout`. It returns null and keeps the original
+        // block on failure, as `GlobalAlloc` requires. It frees the block if
+        // `new_size` is zero, which is excluded by the contract.
+        debug_assert_ne!(new_size, 0);
         unsafe { libc::aligned_realloc(ptr.cast(), new_size, layout.align()).cast() }
     }
 }
 
+#[cfg(feature = "provide-allocator")]
 #[global_allocator]
 static ALLOCATOR: LibcAllocator = LibcAllocator;
+
+#[cfg(all(test, feature = "std", feature = "provide-allocator"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn realloc() {
+        for align in (0..12).map(|shift| 1usize << shift) {
+            let layout = Layout::from_size_align(16, align).unwrap();
+            unsafe {
+                let ptr = LibcAllocator.alloc(layout);
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % align, 0);
+                for i in 0..16 {
+                    ptr.add(i).write(i as u8);
+                }
+
+                let ptr = LibcAllocator.realloc(ptr, layout, 4096);
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % align, 0);
+                for i in 0..16 {
+                    assert_eq!(ptr.add(i).read(), i as u8);
+                }
+
+                LibcAllocator.dealloc(ptr, Layout::from_size_align(4096, align).unwrap());
+            }
+        }
+    }
+}
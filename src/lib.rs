@@ -9,11 +9,19 @@
 //! - The edge trigger only triggers condition changes after it is enabled. You
 //!   cannot expect an event coming if you change the condition before
 //!   registering the notification.
-//! - A socket can be registered to only one IOCP at a time.
+//! - A socket can be registered to only one IOCP at a time. Registering it with
+//!   another poller fails with an error from the system. With the feature
+//!   `socket-owners`, it is detected beforehand, and fails with
+//!   `ERROR_ALREADY_EXISTS`.
+//!
+//! The function is resolved at runtime, and [`Poller::is_supported`] tells
+//! whether it is available.
 //!
 //! `NtAssociateWaitCompletionPacket` is an undocumented API and it's the back
 //! of thread pool APIs like `RegisterWaitForSingleObject`. We use it to avoid
-//! starting thread pools. It only supports `Oneshot` mode.
+//! starting thread pools. It only supports `Oneshot` mode natively, and the
+//! `Level` mode is emulated by associating the waitable again after its event
+//! is received.
 
 #![feature(allocator_api, try_blocks)]
 #![warn(missing_docs)]
@@ -21,39 +29,102 @@
 
 extern crate alloc;
 
+#[cfg(feature = "afd")]
+mod afd;
+mod backend;
+mod events;
 pub mod ffi;
+mod interest;
 mod io;
 mod lock;
 mod map;
+mod notify;
+mod owners;
+mod registration;
+mod stats;
+mod timer;
+#[cfg(feature = "trace")]
+mod trace;
 mod wait;
+mod waker;
 
-use core::{mem::MaybeUninit, ptr::null_mut, time::Duration};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    ffi::{c_int, c_void},
+    fmt::{self, Debug},
+    mem::MaybeUninit,
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
 
+use backend::{Backend, SocketBackend};
+pub use events::{EventRing, Events};
 use hashbrown::TryReserveError;
+pub use interest::{Interest, Readiness};
+#[cfg(feature = "std")]
+pub use io::AsPollSocket;
 use io::OwnedHandle;
-pub use io::{Error, Result};
+pub use io::{Error, RegistrationError, Result};
+use lock::{RwLock, RwLockReadGuard};
 use map::HashMap;
-use wait::WaitCompletionPacket;
+pub use registration::Registration;
+#[cfg(feature = "stats")]
+pub use stats::PollerStats;
+use stats::{Counter, Counters};
+use timer::WaitableTimer;
+use wait::{CancelResult, PacketPool};
+pub use waker::ThreadWaker;
 use windows_sys::Win32::{
     Foundation::{
-        BOOLEAN, ERROR_ALREADY_EXISTS, ERROR_NOT_ENOUGH_MEMORY, ERROR_NOT_ENOUGH_QUOTA,
-        ERROR_NOT_FOUND, ERROR_SUCCESS, HANDLE, INVALID_HANDLE_VALUE, NTSTATUS,
-        RtlNtStatusToDosError, STATUS_SUCCESS, STATUS_TIMEOUT, STATUS_USER_APC, WAIT_TIMEOUT,
+        BOOLEAN, ERROR_ABANDONED_WAIT_0, ERROR_ALREADY_EXISTS, ERROR_BUSY, ERROR_INVALID_HANDLE,
+        ERROR_INVALID_PARAMETER, ERROR_NOT_ENOUGH_MEMORY, ERROR_NOT_ENOUGH_QUOTA, ERROR_NOT_FOUND,
+        ERROR_NOT_SUPPORTED, FILETIME, HANDLE, INVALID_HANDLE_VALUE, NTSTATUS,
+        RtlNtStatusToDosError, STATUS_ABANDONED_WAIT_0, STATUS_SUCCESS, STATUS_TIMEOUT,
+        STATUS_USER_APC,
     },
     Networking::WinSock::{
-        ProcessSocketNotifications, SOCK_NOTIFY_EVENT_ERR, SOCK_NOTIFY_EVENT_HANGUP,
-        SOCK_NOTIFY_EVENT_IN, SOCK_NOTIFY_EVENT_OUT, SOCK_NOTIFY_EVENT_REMOVE,
-        SOCK_NOTIFY_OP_DISABLE, SOCK_NOTIFY_OP_ENABLE, SOCK_NOTIFY_OP_REMOVE,
-        SOCK_NOTIFY_REGISTER_EVENT_HANGUP, SOCK_NOTIFY_REGISTER_EVENT_IN,
-        SOCK_NOTIFY_REGISTER_EVENT_NONE, SOCK_NOTIFY_REGISTER_EVENT_OUT, SOCK_NOTIFY_REGISTRATION,
-        SOCK_NOTIFY_TRIGGER_EDGE, SOCK_NOTIFY_TRIGGER_LEVEL, SOCK_NOTIFY_TRIGGER_ONESHOT,
-        SOCK_NOTIFY_TRIGGER_PERSISTENT, SOCKET,
+        SO_TYPE, SOCK_NOTIFY_EVENT_ERR, SOCK_NOTIFY_EVENT_HANGUP, SOCK_NOTIFY_EVENT_IN,
+        SOCK_NOTIFY_EVENT_OUT, SOCK_NOTIFY_EVENT_REMOVE, SOCKET, SOL_SOCKET, WSAENOTSOCK,
+        WSAGetLastError, WSANOTINITIALISED, getsockopt,
     },
-    System::IO::{
-        CreateIoCompletionPort, OVERLAPPED, OVERLAPPED_ENTRY, PostQueuedCompletionStatus,
+    System::{
+        IO::{
+            CreateIoCompletionPort, IO_STATUS_BLOCK, OVERLAPPED, OVERLAPPED_ENTRY,
+            PostQueuedCompletionStatus,
+        },
+        SystemInformation::GetTickCount64,
     },
 };
 
+#[link(name = "ntdll")]
+unsafe extern "system" {
+    fn NtRemoveIoCompletionEx(
+        handle: HANDLE,
+        information: *mut MaybeUninit<OVERLAPPED_ENTRY>,
+        count: u32,
+        removed: *mut u32,
+        timeout: Option<&mut u64>,
+        alertable: BOOLEAN,
+    ) -> NTSTATUS;
+
+    fn NtSetInformationFile(
+        handle: HANDLE,
+        iosb: *mut IO_STATUS_BLOCK,
+        information: *const c_void,
+        length: u32,
+        class: i32,
+    ) -> NTSTATUS;
+
+    fn NtSetIoCompletion(
+        handle: HANDLE,
+        key: usize,
+        overlapped: *mut OVERLAPPED,
+        status: NTSTATUS,
+        information: usize,
+    ) -> NTSTATUS;
+}
+
 /// The mode in which the poller waits for I/O events.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[non_exhaustive]
@@ -93,307 +164,2565 @@ pub enum PollMode {
     EdgeOneshot,
 }
 
+/// The outcome of [`Poller::wait_ex`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum WaitOutcome {
+    /// Some events are received.
+    Events(usize),
+
+    /// The timeout elapsed without any event.
+    TimedOut,
+
+    /// The alertable wait was interrupted by a user APC.
+    Interrupted,
+}
+
+impl WaitOutcome {
+    /// Number of the received events.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Events(len) => *len,
+            Self::TimedOut | Self::Interrupted => 0,
+        }
+    }
+
+    /// Whether no event is received.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// How the timeout is rounded to the 100ns ticks of the system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum Rounding {
+    /// Round up, so that the wait never returns earlier than the timeout.
+    #[default]
+    Up,
+
+    /// Round down, which may return earlier, but never later than needed.
+    Down,
+
+    /// Round to the nearest tick.
+    Nearest,
+}
+
+/// The order of the events received in one wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum Fairness {
+    /// The order of the port, which is mostly FIFO.
+    #[default]
+    Fifo,
+
+    /// Rotate the starting event across successive waits.
+    ///
+    /// A busy level-triggered socket could be queued again immediately, and
+    /// be handled first in every wait. Rotating gives the others a chance to
+    /// be handled first. It only reorders the events received in one wait,
+    /// and doesn't change which entries are dequeued from the port.
+    RoundRobin,
+}
+
 /// Interface to kqueue.
-#[derive(Debug)]
+///
+/// The poller is `Sync`. Multiple threads could [`Poller::wait`] on a shared
+/// reference concurrently, while the others add, modify, delete and post.
+/// Each event is received by exactly one of the waits, see also
+/// [`Poller::with_concurrency`].
+///
+/// The remove events [`Poller::delete`] and [`Poller::modify`] wait for are
+/// taken from the concurrent waits, and not delivered. However, an event
+/// received by another wait just before the deletion may still be handled
+/// after [`Poller::delete`] returns, so events with a deleted key should be
+/// tolerated.
 pub struct Poller {
     /// The I/O completion port.
     port: OwnedHandle,
 
+    /// The sockets and waitables registered with this poller.
+    ///
+    /// It is locked per poller, so that the registrations could be updated
+    /// while other threads are waiting.
+    registry: RwLock<Registry>,
+
+    /// The backend registering sockets.
+    ///
+    /// It is shared between the cloned pollers of the same port.
+    backend: Arc<Backend>,
+
+    /// The max time to wait for the `SOCK_NOTIFY_EVENT_REMOVE` event, in
+    /// nanoseconds.
+    remove_timeout: AtomicU64,
+
+    /// Whether to forget the sockets on `SOCK_NOTIFY_EVENT_REMOVE` events.
+    auto_cleanup: AtomicBool,
+
+    /// Whether the waits without the `alertable` argument are alertable.
+    alertable: AtomicBool,
+
+    /// The order of the received events, a [`Fairness`] as `u8`.
+    fairness: AtomicU8,
+
+    /// The count of the waits, to rotate the received events.
+    rotation: AtomicUsize,
+
+    /// The counters, only maintained with the feature `stats`.
+    stats: Counters,
+
+    /// Whether any socket has been added by [`Poller::add_split`]. It is not
+    /// reset, and only saves locking the registry in the waits otherwise.
+    splitting: AtomicBool,
+
+    /// Whether a notification packet is queued and not received yet.
+    ///
+    /// It is shared between the cloned pollers of the same port.
+    notified: Arc<AtomicBool>,
+
+    /// Whether [`Poller::poll_once`] is in progress, since its key is shared.
+    ///
+    /// It is shared between the cloned pollers of the same port.
+    polling_once: Arc<AtomicBool>,
+}
+
+unsafe impl Send for Poller {}
+unsafe impl Sync for Poller {}
+
+impl Drop for Poller {
+    /// Removes the registrations before the port is closed, so that the
+    /// sockets and handles could be added to another poller. The removal of
+    /// the sockets is not waited for.
+    fn drop(&mut self) {
+        *self.remove_timeout.get_mut() = 0;
+        self.clear().ok();
+    }
+}
+
+impl Debug for Poller {
+    /// Shows the numbers of the registrations, or lists them with their keys
+    /// in the alternate mode.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alternate = f.alternate();
+        let registry = self.registry.read();
+        let mut s = f.debug_struct("Poller");
+        s.field("port", &self.port.as_raw_handle());
+        if alternate {
+            s.field(
+                "sockets",
+                &RegisteredKeys(&registry.sources, |attr| attr.key),
+            )
+            .field(
+                "waitables",
+                &RegisteredKeys(&registry.waitables, |attr| attr.key),
+            )
+            .field("handles", &RegisteredKeys(&registry.handles, |key| *key));
+        } else {
+            s.field("sockets", &registry.sources.len())
+                .field("waitables", &registry.waitables.len())
+                .field("handles", &registry.handles.len());
+        }
+        s.field("remove_timeout", &self.remove_timeout()).finish()
+    }
+}
+
+/// Formats the registrations as a map from the sockets or handles to their
+/// keys.
+struct RegisteredKeys<'a, K, V>(&'a HashMap<K, V>, fn(&V) -> usize);
+
+impl<K: Debug, V> Debug for RegisteredKeys<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(k, v)| (k, (self.1)(v))))
+            .finish()
+    }
+}
+
+/// The registration state of a [`Poller`].
+#[derive(Debug)]
+struct Registry {
     /// The state of the sources registered with this poller.
     ///
     /// Each source is keyed by its raw socket ID.
-    sources: HashMap<SOCKET, usize>,
+    sources: HashMap<SOCKET, SourceAttr>,
+
+    /// The reverse lookup from completion keys to sockets.
+    ///
+    /// It is only tracked for pollers created by
+    /// [`Poller::with_socket_lookup`].
+    socket_keys: Option<HashMap<usize, SOCKET>>,
+
+    /// The completion keys in use by sockets and waitables.
+    ///
+    /// It is only tracked for pollers created by
+    /// [`Poller::with_unique_keys`].
+    unique_keys: Option<HashMap<usize, ()>>,
 
     /// The state of the waitable handles registered with this poller.
     waitables: HashMap<HANDLE, WaitableAttr>,
+
+    /// The unused packets of the deleted waitables, to be reused.
+    packet_pool: PacketPool,
+
+    /// The file handles associated with the port, and their keys.
+    handles: HashMap<HANDLE, usize>,
+
+    /// The write keys of the sockets added by [`Poller::add_split`], keyed by
+    /// their read keys.
+    splits: HashMap<usize, usize>,
 }
 
-unsafe impl Send for Poller {}
-unsafe impl Sync for Poller {}
+impl Registry {
+    const fn new() -> Self {
+        Self {
+            sources: HashMap::new(),
+            socket_keys: None,
+            unique_keys: None,
+            waitables: HashMap::new(),
+            packet_pool: PacketPool::new(),
+            handles: HashMap::new(),
+            splits: HashMap::new(),
+        }
+    }
+
+    fn with_limit(limit: usize) -> Result<Self> {
+        Ok(Self {
+            sources: HashMap::try_with_limit(limit).map_err(map_try_reserve_error)?,
+            socket_keys: None,
+            unique_keys: None,
+            waitables: HashMap::try_with_limit(limit).map_err(map_try_reserve_error)?,
+            packet_pool: PacketPool::new(),
+            handles: HashMap::try_with_limit(limit).map_err(map_try_reserve_error)?,
+            // A split socket is also a source, so it never exceeds the limit.
+            splits: HashMap::try_with_limit(limit).map_err(map_try_reserve_error)?,
+        })
+    }
+
+    fn track_key(&mut self, key: usize, socket: SOCKET) -> Result<()> {
+        if let Some(keys) = &mut self.socket_keys {
+            keys.remove(&key);
+            keys.try_insert(key, socket)
+                .map_err(map_try_reserve_error)?;
+        }
+        Ok(())
+    }
+
+    fn untrack_key(&mut self, key: usize, socket: SOCKET) {
+        if let Some(keys) = &mut self.socket_keys
+            && keys.get(&key) == Some(&socket)
+        {
+            keys.remove(&key);
+        }
+    }
+
+    /// Checks that the key is not in use, and reserves the room for it.
+    fn check_unique_key(&mut self, key: usize) -> Result<()> {
+        if let Some(keys) = &mut self.unique_keys {
+            if keys.contains_key(&key) {
+                return Err(Error::new(ERROR_ALREADY_EXISTS));
+            }
+            keys.try_reserve(1).map_err(map_try_reserve_error)?;
+        }
+        Ok(())
+    }
+
+    /// Marks the key in use. It should be checked by
+    /// [`Registry::check_unique_key`] first.
+    fn claim_key(&mut self, key: usize) {
+        if let Some(keys) = &mut self.unique_keys {
+            // The room has been reserved.
+            keys.try_insert(key, ()).ok();
+        }
+    }
+
+    fn release_key(&mut self, key: usize) {
+        if let Some(keys) = &mut self.unique_keys {
+            keys.remove(&key);
+        }
+    }
+
+    /// Moves the write key of a split socket to the new read key.
+    fn move_split(&mut self, old: usize, new: usize) {
+        if let Some(write_key) = self.splits.remove(&old) {
+            // The room is freed by the removal.
+            self.splits.try_insert(new, write_key).ok();
+        }
+    }
+
+    /// Forgets the write key of a split socket, and releases it.
+    fn untrack_split(&mut self, key: usize) {
+        if let Some(write_key) = self.splits.remove(&key) {
+            self.release_key(write_key);
+        }
+    }
+}
+
+/// The iterator returned by [`Poller::sockets`].
+struct Sockets<'a> {
+    // Dropped before the guard.
+    keys: map::Keys<'a, SOCKET, SourceAttr>,
+    _guard: RwLockReadGuard<'a, Registry>,
+}
+
+impl<'a> Sockets<'a> {
+    fn new(guard: RwLockReadGuard<'a, Registry>) -> Self {
+        // SAFETY: the registry lives as long as the lock, and it is not
+        // modified while the guard is held.
+        let registry = unsafe { &*RwLockReadGuard::rwlock(&guard).data_ptr() };
+        Self {
+            keys: registry.sources.keys(),
+            _guard: guard,
+        }
+    }
+}
+
+impl Iterator for Sockets<'_> {
+    type Item = SOCKET;
+
+    fn next(&mut self) -> Option<SOCKET> {
+        self.keys.next().copied()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+/// The registered key, interest and mode of a socket.
+#[derive(Debug, Clone, Copy)]
+struct SourceAttr {
+    key: usize,
+    interest: Interest,
+    mode: PollMode,
+    /// Whether the registration is enabled, see [`Poller::disable`].
+    enabled: bool,
+}
+
+impl SourceAttr {
+    /// The attributes passed to the backend, without interest if disabled.
+    fn registered(&self) -> Self {
+        if self.enabled {
+            *self
+        } else {
+            Self {
+                interest: Interest::NONE,
+                ..*self
+            }
+        }
+    }
+}
 
-/// A waitable object with key and [`WaitCompletionPacket`].
+/// A waitable object with key, events, mode and [`WaitCompletionPacket`].
 ///
 /// [`WaitCompletionPacket`]: wait::WaitCompletionPacket
 #[derive(Debug)]
 struct WaitableAttr {
     key: usize,
+    events: u32,
+    mode: PollMode,
+    status: NTSTATUS,
+    packet: wait::WaitCompletionPacket,
+    /// Whether the packet is associated and its event is not received yet.
+    armed: AtomicBool,
+    timeout: Option<WaitableTimeout>,
+    /// The timer created by [`Poller::add_timer`].
+    owned_timer: Option<WaitableTimer>,
+}
+
+/// The timer and its packet of a waitable with timeout.
+#[derive(Debug)]
+struct WaitableTimeout {
+    timer: WaitableTimer,
     packet: wait::WaitCompletionPacket,
 }
 
+impl WaitableTimeout {
+    /// Cancels the timer and its packet.
+    fn cancel(&self) -> Result<CancelResult> {
+        self.timer.cancel()?;
+        self.packet.cancel()
+    }
+
+    /// Cancels the timer and its packet, and removes the queued completion.
+    fn cancel_and_remove(&self) -> Result<CancelResult> {
+        self.timer.cancel()?;
+        self.packet.cancel_and_remove()
+    }
+}
+
 impl Poller {
+    /// Whether `ProcessSocketNotifications` is available on this system.
+    ///
+    /// It is only present after Windows 21H1. If not supported, the sockets
+    /// are polled with `\Device\Afd` when the feature `afd` is enabled,
+    /// which doesn't support the edge modes. Otherwise the socket operations
+    /// fail with `ERROR_NOT_SUPPORTED`, while the waitables still work.
+    pub fn is_supported() -> bool {
+        notify::is_supported()
+    }
+
     /// Creates a new poller.
     pub fn new() -> Result<Self> {
-        let handle = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, null_mut(), 0, 0) };
+        Self::with_concurrency(0)
+    }
+
+    /// Creates a new poller, which allows at most `threads` threads to run
+    /// concurrently after receiving events. Zero means the number of the
+    /// processors, the same as [`Poller::new`].
+    ///
+    /// When multiple threads wait on the poller, or its clones sharing the same
+    /// port, the system doesn't release a wait as long as `threads` threads,
+    /// which have received events, are running and not blocked elsewhere. A
+    /// single-threaded event loop could set it to `1`.
+    pub fn with_concurrency(threads: u32) -> Result<Self> {
+        let handle =
+            unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, null_mut(), 0, threads) };
         if handle.is_null() {
-            return Err(Error::last_os_error());
+            return Err(Error::last_os_error().with_context("CreateIoCompletionPort"));
         }
 
         let port = unsafe { OwnedHandle::from_raw_handle(handle) };
+        let backend = Arc::try_new(Backend::new(port.as_raw_handle())?)
+            .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+        let notified = Arc::try_new(AtomicBool::new(false))
+            .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+        let polling_once = Arc::try_new(AtomicBool::new(false))
+            .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
         Ok(Poller {
             port,
-            sources: HashMap::new(),
-            waitables: HashMap::new(),
+            registry: RwLock::new(Registry::new()),
+            backend,
+            remove_timeout: AtomicU64::new(DEFAULT_REMOVE_TIMEOUT.as_nanos() as _),
+            auto_cleanup: AtomicBool::new(false),
+            alertable: AtomicBool::new(false),
+            fairness: AtomicU8::new(Fairness::Fifo as u8),
+            rotation: AtomicUsize::new(0),
+            stats: Counters::new(),
+            splitting: AtomicBool::new(false),
+            notified,
+            polling_once,
+        })
+    }
+
+    /// Creates a new poller with fixed capacity.
+    ///
+    /// At most `capacity` sockets, `capacity` waitables and `capacity` file
+    /// handles could be registered. The memory is allocated up front and never
+    /// reallocated, including the tracked keys of the split sockets.
+    /// Registering more returns `ERROR_NOT_ENOUGH_QUOTA`.
+    pub fn with_capacity(capacity: usize) -> Result<Self> {
+        let mut poller = Self::new()?;
+        *poller.registry.get_mut() = Registry::with_limit(capacity)?;
+        Ok(poller)
+    }
+
+    /// Creates a new poller which polls the sockets with `\Device\Afd`, even
+    /// if `ProcessSocketNotifications` is supported.
+    ///
+    /// It is the fallback before Windows 21H1, which doesn't support the edge
+    /// modes. With the feature `force-afd`, all pollers poll with AFD, and
+    /// this is the same as [`Poller::new`].
+    #[cfg(feature = "afd")]
+    pub fn with_afd() -> Result<Self> {
+        let mut poller = Self::new()?;
+        poller.backend = Arc::try_new(Backend::afd(poller.port.as_raw_handle())?)
+            .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+        Ok(poller)
+    }
+
+    /// Creates a new poller which tracks the sockets of completion keys.
+    ///
+    /// See [`Poller::socket_for_key`].
+    pub fn with_socket_lookup() -> Result<Self> {
+        let mut poller = Self::new()?;
+        poller.registry.get_mut().socket_keys = Some(HashMap::new());
+        Ok(poller)
+    }
+
+    /// Creates a new poller which rejects duplicate completion keys.
+    ///
+    /// Registering a socket or a waitable with a key already used by another
+    /// one fails with `ERROR_ALREADY_EXISTS`, so that the source of an event
+    /// could be told by its key.
+    pub fn with_unique_keys() -> Result<Self> {
+        let mut poller = Self::new()?;
+        poller.registry.get_mut().unique_keys = Some(HashMap::new());
+        Ok(poller)
+    }
+
+    /// Creates a new poller sharing the same I/O completion port.
+    ///
+    /// The port handle is duplicated, so both pollers could [`Poller::wait`]
+    /// on it. The registered sockets and waitables are tracked per poller, and
+    /// the new one starts empty. However, the registrations are shared at the
+    /// kernel level: events of sockets added to one poller are delivered to
+    /// the waits of both.
+    pub fn try_clone(&self) -> Result<Self> {
+        let port = self.port.try_clone()?;
+        let current = self.registry.read();
+        let mut registry = match current.sources.limit() {
+            Some(limit) => Registry::with_limit(limit)?,
+            None => Registry::new(),
+        };
+        if current.socket_keys.is_some() {
+            registry.socket_keys = Some(HashMap::new());
+        }
+        if current.unique_keys.is_some() {
+            registry.unique_keys = Some(HashMap::new());
+        }
+        drop(current);
+        Ok(Poller {
+            port,
+            registry: RwLock::new(registry),
+            backend: self.backend.clone(),
+            remove_timeout: AtomicU64::new(self.remove_timeout.load(Ordering::Relaxed)),
+            auto_cleanup: AtomicBool::new(self.auto_cleanup.load(Ordering::Relaxed)),
+            alertable: AtomicBool::new(self.alertable.load(Ordering::Relaxed)),
+            fairness: AtomicU8::new(self.fairness.load(Ordering::Relaxed)),
+            rotation: AtomicUsize::new(0),
+            stats: Counters::new(),
+            splitting: AtomicBool::new(false),
+            notified: self.notified.clone(),
+            polling_once: self.polling_once.clone(),
         })
     }
 
-    /// Adds a new socket.
-    pub fn add(&mut self, socket: SOCKET, interest: Event, mode: PollMode) -> Result<()> {
-        if self.sources.contains_key(&socket) {
-            return Err(Error(ERROR_ALREADY_EXISTS));
+    /// Identifies the port in the process-wide owners of the sockets. The
+    /// backend is shared between the cloned pollers of the same port.
+    fn owner(&self) -> usize {
+        Arc::as_ptr(&self.backend) as usize
+    }
+
+    /// Forgets the socket and releases its key, without touching the
+    /// registration. Returns the key if it was added.
+    fn forget_socket(&self, registry: &mut Registry, socket: SOCKET) -> Option<usize> {
+        let key = registry.sources.remove(&socket)?.key;
+        registry.release_key(key);
+        registry.untrack_key(key, socket);
+        registry.untrack_split(key);
+        owners::release(socket, self.owner());
+        Some(key)
+    }
+
+    /// The raw handle of the I/O completion port.
+    ///
+    /// The handle is owned by the poller. It could be passed to other native
+    /// APIs, e.g., to associate a file handle with `CreateIoCompletionPort`.
+    /// The completions posted externally are received by [`Poller::wait`] as
+    /// is, and their keys should not collide with the registered ones.
+    pub fn as_raw_handle(&self) -> HANDLE {
+        self.port.as_raw_handle()
+    }
+
+    /// Adds a new socket.
+    pub fn add(
+        &self,
+        socket: SOCKET,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        check_key(key)?;
+        let mut registry = self.registry.write();
+        self.add_locked(&mut registry, socket, key, interest, mode)
+    }
+
+    fn add_locked(
+        &self,
+        registry: &mut Registry,
+        socket: SOCKET,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        if registry.sources.contains_key(&socket) {
+            return Err(Error::new(ERROR_ALREADY_EXISTS).with_context("add socket"));
+        }
+        if registry.sources.is_full() {
+            return Err(Error::new(ERROR_NOT_ENOUGH_QUOTA));
+        }
+        registry.check_unique_key(key)?;
+        owners::claim(socket, self.owner())?;
+        registry
+            .sources
+            .try_insert(
+                socket,
+                SourceAttr {
+                    key,
+                    interest,
+                    mode,
+                    enabled: true,
+                },
+            )
+            .map_err(map_try_reserve_error)
+            .inspect_err(|_| owners::release(socket, self.owner()))?;
+        registry.claim_key(key);
+
+        let res = registry.track_key(key, socket).and_then(|()| {
+            self.backend.update(
+                self.port.as_raw_handle(),
+                socket,
+                &SourceAttr {
+                    key,
+                    interest,
+                    mode,
+                    enabled: true,
+                },
+            )
+        });
+        if let Err(e) = res {
+            // Roll back, so that the socket could be added again.
+            registry.sources.remove(&socket);
+            registry.release_key(key);
+            registry.untrack_key(key, socket);
+            owners::release(socket, self.owner());
+            // The system rejects a socket registered with another port.
+            return Err(if e.0 == ERROR_INVALID_PARAMETER && is_socket(socket) {
+                Error::new(ERROR_ALREADY_EXISTS).with_context("socket registered with another port")
+            } else {
+                e
+            });
+        }
+        self.stats.add(Counter::Registrations, 1);
+        Ok(())
+    }
+
+    /// Adds a new socket, and returns a [`Registration`] deleting it when
+    /// dropped.
+    ///
+    /// It coexists with [`Poller::add`] and [`Poller::delete`]. Deleting the
+    /// socket manually is harmless, but it should not be added again while
+    /// the guard lives.
+    pub fn register(
+        &self,
+        socket: SOCKET,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<Registration<'_>> {
+        self.add(socket, key, interest, mode)?;
+        Ok(Registration::new(self, socket))
+    }
+
+    /// Adds a new socket, and delivers its readiness of reading and writing
+    /// with different keys.
+    ///
+    /// The socket is registered with `read_key` for all events. A received
+    /// event with `SOCK_NOTIFY_EVENT_OUT` is split, so that the writable part
+    /// is delivered with `write_key`, and the rest, including hangup and
+    /// error, with `read_key`. The writable parts are appended after the
+    /// received events, or posted back to the port if the buffer is full.
+    ///
+    /// The socket is modified and deleted with `read_key` as other sockets,
+    /// and `write_key` is kept.
+    pub fn add_split(
+        &self,
+        socket: SOCKET,
+        read_key: usize,
+        write_key: usize,
+        mode: PollMode,
+    ) -> Result<()> {
+        check_key(read_key)?;
+        check_key(write_key)?;
+        if read_key == write_key {
+            return Err(Error::new(ERROR_INVALID_PARAMETER).with_context("add split socket"));
+        }
+        let mut registry = self.registry.write();
+        registry.check_unique_key(write_key)?;
+        registry
+            .splits
+            .try_reserve(1)
+            .map_err(map_try_reserve_error)?;
+        registry.claim_key(write_key);
+        if let Err(e) = self.add_locked(&mut registry, socket, read_key, Interest::ALL, mode) {
+            registry.release_key(write_key);
+            return Err(e);
+        }
+        // The room has been reserved.
+        registry.splits.try_insert(read_key, write_key).ok();
+        self.splitting.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Modifies an existing socket.
+    ///
+    /// Changing the interest or the mode updates the registration in place.
+    /// Changing the key removes the old registration and waits for the
+    /// removal first, which is much more expensive. If the removal or the new
+    /// registration fails, e.g., timed out, the socket is deleted from the
+    /// poller.
+    pub fn modify(
+        &self,
+        socket: SOCKET,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        self.modify_replace(socket, key, interest, mode)?;
+        Ok(())
+    }
+
+    /// Modifies an existing socket like [`Poller::modify`], and returns the
+    /// previous key, interest and mode.
+    pub fn modify_replace(
+        &self,
+        socket: SOCKET,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<(usize, Interest, PollMode)> {
+        let old = self.update_attr(socket, |_| SourceAttr {
+            key,
+            interest,
+            mode,
+            enabled: true,
+        })?;
+        Ok((old.key, old.interest, old.mode))
+    }
+
+    /// Adds the socket, or modifies it like [`Poller::modify`] if it has
+    /// already been added.
+    ///
+    /// The socket is looked up and added or modified with the registry
+    /// locked, unless the key is changed.
+    pub fn add_or_modify(
+        &self,
+        socket: SOCKET,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        check_key(key)?;
+        loop {
+            let mut registry = self.registry.write();
+            match registry.sources.get(&socket) {
+                None => return self.add_locked(&mut registry, socket, key, interest, mode),
+                Some(attr) if attr.key == key => {
+                    return self.modify_locked(&mut registry, socket, key, interest, mode);
+                }
+                Some(_) => {}
+            }
+            // Changing the key waits for the removal without the lock.
+            drop(registry);
+            match self.modify(socket, key, interest, mode) {
+                // The socket is deleted in the meantime, add it again.
+                Err(e) if e.0 == ERROR_NOT_FOUND => {}
+                res => return res,
+            }
+        }
+    }
+
+    /// Adds the interest to an existing socket, and changes its mode.
+    ///
+    /// The interest already registered is kept.
+    pub fn add_interest(&self, socket: SOCKET, extra: Interest, mode: PollMode) -> Result<()> {
+        self.update_attr(socket, |old| SourceAttr {
+            interest: old.interest.add(extra),
+            mode,
+            ..*old
+        })?;
+        Ok(())
+    }
+
+    /// Removes the interest from an existing socket.
+    ///
+    /// The socket is disabled if no interest is left, but not deleted.
+    pub fn remove_interest(&self, socket: SOCKET, to_clear: Interest) -> Result<()> {
+        self.update_attr(socket, |old| SourceAttr {
+            interest: old.interest.remove(to_clear),
+            ..*old
+        })?;
+        Ok(())
+    }
+
+    /// Disables the registration of an existing socket without removing it.
+    ///
+    /// The interest and the mode are kept, and restored by
+    /// [`Poller::enable`]. Adding or removing interest keeps it disabled,
+    /// while [`Poller::modify`] enables it again.
+    pub fn disable(&self, socket: SOCKET) -> Result<()> {
+        self.set_enabled(socket, false)
+    }
+
+    /// Enables the registration of a socket disabled by [`Poller::disable`],
+    /// with the interest and the mode before.
+    pub fn enable(&self, socket: SOCKET) -> Result<()> {
+        self.set_enabled(socket, true)
+    }
+
+    /// Re-enables the registration of an existing socket with the stored
+    /// interest and mode, e.g., after a oneshot event is received.
+    ///
+    /// It is cheaper than [`Poller::modify`] with the same arguments, and
+    /// avoids passing a different interest by mistake. A socket disabled by
+    /// [`Poller::disable`] is kept disabled.
+    pub fn rearm(&self, socket: SOCKET) -> Result<()> {
+        let registry = self.registry.read();
+        let attr = registry
+            .sources
+            .get(&socket)
+            .ok_or(Error::new(ERROR_NOT_FOUND).with_context("rearm socket"))?;
+        self.backend
+            .update(self.port.as_raw_handle(), socket, &attr.registered())
+    }
+
+    fn set_enabled(&self, socket: SOCKET, enabled: bool) -> Result<()> {
+        let mut registry = self.registry.write();
+        let attr = registry
+            .sources
+            .get_mut(&socket)
+            .ok_or(Error::new(ERROR_NOT_FOUND).with_context("modify socket"))?;
+        let new = SourceAttr { enabled, ..*attr };
+        self.backend
+            .update(self.port.as_raw_handle(), socket, &new.registered())?;
+        attr.enabled = enabled;
+        Ok(())
+    }
+
+    /// Updates the registration of a socket with the attributes computed from
+    /// the previous ones, and returns the previous ones.
+    fn update_attr(
+        &self,
+        socket: SOCKET,
+        f: impl FnOnce(&SourceAttr) -> SourceAttr,
+    ) -> Result<SourceAttr> {
+        let mut registry = self.registry.write();
+        let old = *registry
+            .sources
+            .get(&socket)
+            .ok_or(Error::new(ERROR_NOT_FOUND).with_context("modify socket"))?;
+        let new = f(&old);
+        check_key(new.key)?;
+
+        if old.key == new.key {
+            self.update_locked(&mut registry, socket, new)?;
+            return Ok(old);
+        }
+
+        // To change the key, remove the old registration and wait for REMOVE
+        // event. The new attributes are stored first, and the registry is not
+        // locked while waiting.
+        registry.check_unique_key(new.key)?;
+        registry.track_key(new.key, socket)?;
+        registry.release_key(old.key);
+        registry.claim_key(new.key);
+        registry.untrack_key(old.key, socket);
+        registry.move_split(old.key, new.key);
+        if let Some(attr) = registry.sources.get_mut(&socket) {
+            *attr = new;
+        }
+        drop(registry);
+        let res = self.backend.remove(
+            self.port.as_raw_handle(),
+            socket,
+            old.key,
+            self.remove_timeout(),
+            &self.stats,
+        );
+        let mut registry = self.registry.write();
+        if let Err(e) = res {
+            // The old registration may still be there, or removed without
+            // the event received in time. The stored attributes can't be
+            // trusted, so forget the socket.
+            self.forget_socket(&mut registry, socket);
+            return Err(e);
+        }
+
+        // The attributes may have been changed by other threads meanwhile.
+        let attr = *registry
+            .sources
+            .get(&socket)
+            .ok_or(Error::new(ERROR_NOT_FOUND).with_context("modify socket"))?;
+        if let Err(e) = self
+            .backend
+            .update(self.port.as_raw_handle(), socket, &attr.registered())
+        {
+            // The old registration has been removed, and there is nothing to
+            // roll back to.
+            self.forget_socket(&mut registry, socket);
+            return Err(e);
+        }
+        Ok(old)
+    }
+
+    /// Updates the registration of a socket in place, without changing the
+    /// key.
+    fn update_locked(
+        &self,
+        registry: &mut Registry,
+        socket: SOCKET,
+        new: SourceAttr,
+    ) -> Result<()> {
+        self.backend
+            .update(self.port.as_raw_handle(), socket, &new.registered())?;
+        if let Some(attr) = registry.sources.get_mut(&socket) {
+            *attr = new;
+        }
+        Ok(())
+    }
+
+    /// Modifies an existing socket like [`Poller::modify`] with the registry
+    /// locked. Changing the key fails with `ERROR_BUSY`, as the removal can't
+    /// be waited for with the lock held.
+    fn modify_locked(
+        &self,
+        registry: &mut Registry,
+        socket: SOCKET,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        let old = registry
+            .sources
+            .get(&socket)
+            .ok_or(Error::new(ERROR_NOT_FOUND).with_context("modify socket"))?;
+        if old.key != key {
+            return Err(Error::new(ERROR_BUSY).with_context("modify socket"));
+        }
+        self.update_locked(
+            registry,
+            socket,
+            SourceAttr {
+                key,
+                interest,
+                mode,
+                enabled: true,
+            },
+        )
+    }
+
+    /// Changes the completion key of an existing socket, and keeps the
+    /// interest and the mode.
+    ///
+    /// Like [`Poller::modify`], the old registration is removed and waited
+    /// for, so that the events with the old key are not received after it
+    /// returns.
+    pub fn modify_key(&self, socket: SOCKET, key: usize) -> Result<()> {
+        self.update_attr(socket, |old| SourceAttr { key, ..*old })?;
+        Ok(())
+    }
+
+    /// Deletes a socket.
+    pub fn delete(&self, socket: SOCKET) -> Result<()> {
+        let key = self
+            .forget_socket(&mut self.registry.write(), socket)
+            .ok_or(Error::new(ERROR_NOT_FOUND).with_context("delete socket"))?;
+        // The registration has been forgotten, wait for the removal unlocked.
+        match self.backend.remove(
+            self.port.as_raw_handle(),
+            socket,
+            key,
+            self.remove_timeout(),
+            &self.stats,
+        ) {
+            // The socket has been closed, and the system has removed the
+            // registration with it.
+            Err(e)
+                if e.registration_error() == RegistrationError::NotSocket
+                    || e.0 == ERROR_INVALID_HANDLE =>
+            {
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /// Adds a new socket, see [`Poller::add`].
+    ///
+    /// The raw `SOCKET` is taken from `io`, e.g., a `TcpStream` or a
+    /// `socket2::Socket`.
+    #[cfg(feature = "std")]
+    pub fn add_io<S: AsPollSocket + ?Sized>(
+        &self,
+        io: &S,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        self.add(io.as_poll_socket(), key, interest, mode)
+    }
+
+    /// Modifies an existing socket, see [`Poller::modify`].
+    #[cfg(feature = "std")]
+    pub fn modify_io<S: AsPollSocket + ?Sized>(
+        &self,
+        io: &S,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        self.modify(io.as_poll_socket(), key, interest, mode)
+    }
+
+    /// Deletes a socket, see [`Poller::delete`].
+    #[cfg(feature = "std")]
+    pub fn delete_io<S: AsPollSocket + ?Sized>(&self, io: &S) -> Result<()> {
+        self.delete(io.as_poll_socket())
+    }
+
+    /// Gets the socket registered with the completion key.
+    ///
+    /// Returns `None` if the poller is not created by
+    /// [`Poller::with_socket_lookup`]. If several sockets share the same key,
+    /// the last registered one is returned.
+    pub fn socket_for_key(&self, key: usize) -> Option<SOCKET> {
+        self.registry
+            .read()
+            .socket_keys
+            .as_ref()?
+            .get(&key)
+            .copied()
+    }
+
+    /// Sets the max time [`Poller::delete`] and [`Poller::modify`] wait for a
+    /// removed registration to be confirmed. The default is 1 second.
+    ///
+    /// When timed out, they return `WAIT_TIMEOUT`. The registration is removed
+    /// anyway, but a `SOCK_NOTIFY_EVENT_REMOVE` event may be received by
+    /// [`Poller::wait`] later.
+    pub fn set_remove_timeout(&self, timeout: Duration) {
+        let nanos = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+        self.remove_timeout.store(nanos, Ordering::Relaxed);
+    }
+
+    fn remove_timeout(&self) -> Duration {
+        Duration::from_nanos(self.remove_timeout.load(Ordering::Relaxed))
+    }
+
+    /// Sets whether [`Poller::wait`] forgets the socket of a received remove
+    /// event, see [`Event::is_removed`]. The default is `false`.
+    ///
+    /// The registration has been torn down by the system, e.g., because the
+    /// socket is closed, and a later [`Poller::delete`] would fail. The socket
+    /// is found by the key of the event, so it is skipped if more than one
+    /// socket is registered with the key. It is also skipped if the socket is
+    /// still open, e.g., the event is left by a timed out removal.
+    pub fn set_auto_cleanup(&self, enabled: bool) {
+        self.auto_cleanup.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Sets whether the waits without the `alertable` argument are alertable,
+    /// i.e., [`Poller::poll`], [`Poller::wait_events`] and
+    /// [`Poller::wait_into_ring`]. The default is `false`.
+    ///
+    /// An alertable wait returns when a user APC is queued to the thread,
+    /// e.g., by [`ThreadWaker::wake`].
+    pub fn set_alertable(&self, alertable: bool) {
+        self.alertable.store(alertable, Ordering::Relaxed);
+    }
+
+    /// The statistics of the poller, with the feature `stats`.
+    ///
+    /// The counters are kept per poller, and start from zero for the cloned
+    /// ones.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> PollerStats {
+        self.stats.snapshot()
+    }
+
+    /// Sets the order of the events received in one wait. The default is
+    /// [`Fairness::Fifo`].
+    ///
+    /// [`Fairness::RoundRobin`] costs a rotation of the events per wait.
+    pub fn set_fairness(&self, fairness: Fairness) {
+        self.fairness.store(fairness as u8, Ordering::Relaxed);
+    }
+
+    /// Reserves capacity for at least `additional` more sockets, so that the
+    /// following [`Poller::add`] calls don't fail for allocation.
+    ///
+    /// For a poller created by [`Poller::with_capacity`], it only checks
+    /// whether there is enough room left.
+    pub fn reserve(&self, additional: usize) -> Result<()> {
+        let mut registry = self.registry.write();
+        if let Some(limit) = registry.sources.limit() {
+            return if registry.sources.len().saturating_add(additional) > limit {
+                Err(Error::new(ERROR_NOT_ENOUGH_QUOTA))
+            } else {
+                Ok(())
+            };
+        }
+        registry
+            .sources
+            .try_reserve(additional)
+            .map_err(map_try_reserve_error)?;
+        if let Some(keys) = &mut registry.socket_keys {
+            keys.try_reserve(additional)
+                .map_err(map_try_reserve_error)?;
+        }
+        if let Some(keys) = &mut registry.unique_keys {
+            keys.try_reserve(additional)
+                .map_err(map_try_reserve_error)?;
+        }
+        Ok(())
+    }
+
+    /// Shrinks the capacity of the registrations as much as possible.
+    ///
+    /// It is O(n) to the registrations, and is intended to be called in idle
+    /// periods, e.g., after many sockets are deleted.
+    pub fn shrink_to_fit(&self) {
+        let mut registry = self.registry.write();
+        registry.sources.shrink_to_fit();
+        if let Some(keys) = &mut registry.socket_keys {
+            keys.shrink_to_fit();
+        }
+        if let Some(keys) = &mut registry.unique_keys {
+            keys.shrink_to_fit();
+        }
+        registry.waitables.shrink_to_fit();
+    }
+
+    /// Gets the key, interest and mode a socket is currently registered with.
+    pub fn interest(&self, socket: SOCKET) -> Option<(usize, Interest, PollMode)> {
+        self.registry
+            .read()
+            .sources
+            .get(&socket)
+            .map(|attr| (attr.key, attr.interest, attr.mode))
+    }
+
+    /// Iterates the sockets registered with this poller.
+    ///
+    /// The registrations are locked for reading until the iterator is
+    /// dropped. Don't add, modify or delete registrations of this poller on
+    /// the same thread meanwhile, or it deadlocks.
+    pub fn sockets(&self) -> impl Iterator<Item = SOCKET> + '_ {
+        Sockets::new(self.registry.read())
+    }
+
+    /// Whether a socket is registered with this poller.
+    pub fn contains_socket(&self, socket: SOCKET) -> bool {
+        self.registry.read().sources.contains_key(&socket)
+    }
+
+    /// Whether a waitable is registered with this poller.
+    pub fn contains_waitable(&self, handle: HANDLE) -> bool {
+        self.registry.read().waitables.contains_key(&handle)
+    }
+
+    /// Whether a file handle is associated by [`Poller::add_handle`].
+    pub fn contains_handle(&self, handle: HANDLE) -> bool {
+        self.registry.read().handles.contains_key(&handle)
+    }
+
+    /// Number of sockets and waitables registered with this poller.
+    pub fn len(&self) -> usize {
+        let registry = self.registry.read();
+        registry.sources.len() + registry.waitables.len()
+    }
+
+    /// Whether no socket or waitable is registered with this poller.
+    pub fn is_empty(&self) -> bool {
+        let registry = self.registry.read();
+        registry.sources.is_empty() && registry.waitables.is_empty()
+    }
+
+    /// Add a new waitable to the poller.
+    ///
+    /// Only [`PollMode::Oneshot`] and [`PollMode::Level`] are supported. The
+    /// level trigger is emulated: the waitable is associated again in
+    /// [`Poller::wait`] after its event is received, which costs one more
+    /// syscall per event. If the waitable is still signaled, the event is
+    /// queued again immediately. Note that an auto-reset event is reset by the
+    /// association, and the waitable may become non-signaled between the
+    /// delivery and the re-association.
+    ///
+    /// If the waitable is already signaled when added, the event is queued
+    /// immediately.
+    pub fn add_waitable(
+        &self,
+        handle: HANDLE,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        self.add_waitable_with_status(handle, key, interest, mode, STATUS_SUCCESS)
+    }
+
+    /// Add a new waitable to the poller like [`Poller::add_waitable`], and
+    /// its events carry the `status`, see [`Event::status`].
+    ///
+    /// The status could be used to distinguish the waitables sharing the
+    /// same key.
+    pub fn add_waitable_with_status(
+        &self,
+        handle: HANDLE,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+        status: NTSTATUS,
+    ) -> Result<()> {
+        let mut registry = self.registry.write();
+        self.add_waitable_locked(&mut registry, handle, key, interest, mode, status)
+    }
+
+    /// Add a new waitable, and keep the timer owned by the poller.
+    fn add_waitable_owned(
+        &self,
+        handle: HANDLE,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+        owned_timer: WaitableTimer,
+    ) -> Result<()> {
+        let mut registry = self.registry.write();
+        self.add_waitable_locked(&mut registry, handle, key, interest, mode, STATUS_SUCCESS)?;
+        if let Some(attr) = registry.waitables.get_mut(&handle) {
+            attr.owned_timer = Some(owned_timer);
+        }
+        Ok(())
+    }
+
+    /// Add new waitables with the same mode in a batch.
+    ///
+    /// The registrations are locked only once, and the packets of the deleted
+    /// waitables are reused. The result of each waitable is returned in
+    /// order, and the outer error is only for allocation failure.
+    pub fn add_waitables(
+        &self,
+        waitables: &[(HANDLE, usize, Interest)],
+        mode: PollMode,
+    ) -> Result<Vec<Result<()>>> {
+        let mut results = Vec::new();
+        results
+            .try_reserve_exact(waitables.len())
+            .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+        let mut registry = self.registry.write();
+        for &(handle, key, interest) in waitables {
+            results.push(self.add_waitable_locked(
+                &mut registry,
+                handle,
+                key,
+                interest,
+                mode,
+                STATUS_SUCCESS,
+            ));
+        }
+        Ok(results)
+    }
+
+    fn add_waitable_locked(
+        &self,
+        registry: &mut Registry,
+        handle: HANDLE,
+        key: usize,
+        interest: Interest,
+        mode: PollMode,
+        status: NTSTATUS,
+    ) -> Result<()> {
+        check_key(key)?;
+        check_waitable_mode(mode)?;
+        if registry.waitables.contains_key(&handle) {
+            return Err(Error::new(ERROR_ALREADY_EXISTS).with_context("add waitable"));
+        }
+        if registry.waitables.is_full() {
+            return Err(Error::new(ERROR_NOT_ENOUGH_QUOTA));
+        }
+        registry.check_unique_key(key)?;
+
+        let events = interest.bits();
+        let packet = registry.packet_pool.take()?;
+        if let Err(e) = packet.associate_with_status(
+            self.port.as_raw_handle(),
+            handle,
+            handle,
+            key,
+            status,
+            events as _,
+        ) {
+            registry.packet_pool.recycle(packet);
+            return Err(e);
+        }
+        registry
+            .waitables
+            .try_insert(
+                handle,
+                WaitableAttr {
+                    key,
+                    events,
+                    mode,
+                    status,
+                    packet,
+                    armed: AtomicBool::new(true),
+                    timeout: None,
+                    owned_timer: None,
+                },
+            )
+            .map_err(map_try_reserve_error)?;
+        registry.claim_key(key);
+        self.stats.add(Counter::Registrations, 1);
+        Ok(())
+    }
+
+    /// Add a new waitable to the poller in oneshot mode, with a timeout.
+    ///
+    /// An event is delivered either when the waitable is signaled, or when
+    /// the timeout elapses. The latter is indicated by [`Event::is_timeout`].
+    /// Once one of them is received, the other is cancelled. However, both
+    /// may be delivered if they happen nearly at the same time.
+    pub fn add_waitable_timeout(
+        &self,
+        handle: HANDLE,
+        key: usize,
+        interest: Interest,
+        timeout: Duration,
+    ) -> Result<()> {
+        check_key(key)?;
+        let mut registry = self.registry.write();
+        if registry.waitables.contains_key(&handle) {
+            return Err(Error::new(ERROR_ALREADY_EXISTS).with_context("add waitable"));
+        }
+        if registry.waitables.is_full() {
+            return Err(Error::new(ERROR_NOT_ENOUGH_QUOTA));
+        }
+        registry.check_unique_key(key)?;
+
+        let events = interest.bits();
+        let timer = WaitableTimer::new()?;
+        let timer_packet = registry.packet_pool.take()?;
+        let packet = registry.packet_pool.take()?;
+        timer.set(timeout, None)?;
+        // The waitable handle is passed as the context of both packets.
+        timer_packet.associate_with_context(
+            self.port.as_raw_handle(),
+            timer.as_raw_handle(),
+            handle,
+            key,
+            (events | EVENT_TIMEOUT) as _,
+        )?;
+        let timeout = WaitableTimeout {
+            timer,
+            packet: timer_packet,
+        };
+        if let Err(e) = packet.associate(self.port.as_raw_handle(), handle, key, events as _) {
+            timeout.cancel().ok();
+            return Err(e);
+        }
+        registry
+            .waitables
+            .try_insert(
+                handle,
+                WaitableAttr {
+                    key,
+                    events,
+                    mode: PollMode::Oneshot,
+                    status: STATUS_SUCCESS,
+                    packet,
+                    armed: AtomicBool::new(true),
+                    timeout: Some(timeout),
+                    owned_timer: None,
+                },
+            )
+            .map_err(map_try_reserve_error)?;
+        registry.claim_key(key);
+        self.stats.add(Counter::Registrations, 1);
+        Ok(())
+    }
+
+    /// Add a new timer to the poller.
+    ///
+    /// The timer is triggered after `due`, and then every `period` if
+    /// specified, with a readable event. The periodic timer is polled in
+    /// level mode, and the periods elapsed before the event is received are
+    /// coalesced.
+    ///
+    /// The returned timer handle is owned by the poller. Delete it with
+    /// [`Poller::delete_waitable`], which also closes it.
+    pub fn add_timer(&self, key: usize, due: Duration, period: Option<Duration>) -> Result<HANDLE> {
+        let timer = WaitableTimer::new()?;
+        timer.set(due, period)?;
+        let handle = timer.as_raw_handle();
+        let mode = if period.is_some() {
+            PollMode::Level
+        } else {
+            PollMode::Oneshot
+        };
+        self.add_waitable_owned(handle, key, Interest::READABLE, mode, timer)?;
+        Ok(handle)
+    }
+
+    /// Update a waitable in the poller.
+    ///
+    /// The timeout set by [`Poller::add_waitable_timeout`] is removed. If the
+    /// interest and the mode are unchanged, and the event is not received
+    /// yet, the registration is kept as is.
+    pub fn modify_waitable(
+        &self,
+        waitable: HANDLE,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        let mut registry = self.registry.write();
+        self.modify_waitable_locked(&mut registry, waitable, interest, mode)
+    }
+
+    fn modify_waitable_locked(
+        &self,
+        registry: &mut Registry,
+        waitable: HANDLE,
+        interest: Interest,
+        mode: PollMode,
+    ) -> Result<()> {
+        check_waitable_mode(mode)?;
+        let Registry {
+            waitables,
+            packet_pool,
+            ..
+        } = registry;
+        let attr = waitables
+            .get_mut(&waitable)
+            .ok_or(Error::new(ERROR_NOT_FOUND).with_context("modify waitable"))?;
+        if attr.timeout.is_none()
+            && attr.events == interest.bits()
+            && attr.mode == mode
+            && *attr.armed.get_mut()
+        {
+            return Ok(());
+        }
+
+        if let Some(timeout) = attr.timeout.take()
+            && timeout.cancel()?.is_reusable()
+        {
+            packet_pool.recycle(timeout.packet);
+        }
+        if attr.packet.cancel()? == CancelResult::Pending {
+            // The signal has been queued to the port, and it is delivered with
+            // the old interest. Associating again would deliver it twice.
+            attr.events = interest.bits();
+            attr.mode = mode;
+            return Ok(());
+        }
+        attr.events = interest.bits();
+        attr.mode = mode;
+        *attr.armed.get_mut() = false;
+        attr.packet.associate_with_status(
+            self.port.as_raw_handle(),
+            waitable,
+            waitable,
+            attr.key,
+            attr.status,
+            attr.events as _,
+        )?;
+        *attr.armed.get_mut() = true;
+        Ok(())
+    }
+
+    /// Deletes all sockets and waitables.
+    ///
+    /// It attempts to remove every registration even if some of them fail,
+    /// and returns the first error. The timers added by
+    /// [`Poller::add_timer`] are closed.
+    ///
+    /// The sockets are deleted one by one like [`Poller::delete`], so that
+    /// the registry is not locked while waiting for their removal.
+    pub fn clear(&self) -> Result<()> {
+        let mut res = Ok(());
+        loop {
+            let socket = self.registry.read().sources.keys().next().copied();
+            let Some(socket) = socket else {
+                break;
+            };
+            res = res.and(self.delete(socket));
+        }
+        let mut registry = self.registry.write();
+        registry.sources.clear();
+        registry.splits.clear();
+        if let Some(keys) = &mut registry.socket_keys {
+            keys.clear();
+        }
+        if let Some(keys) = &mut registry.unique_keys {
+            keys.clear();
+        }
+        for (handle, _) in registry.handles.drain() {
+            res = res.and(disassociate(handle));
+        }
+        let Registry {
+            waitables,
+            packet_pool,
+            ..
+        } = &mut *registry;
+        for (_, attr) in waitables.drain() {
+            if let Some(timeout) = attr.timeout {
+                match timeout.cancel_and_remove() {
+                    Ok(cancel) if cancel.is_reusable() => packet_pool.recycle(timeout.packet),
+                    Ok(_) => {}
+                    Err(e) => res = res.and(Err(e)),
+                }
+            }
+            match attr.packet.cancel_and_remove() {
+                Ok(cancel) if cancel.is_reusable() => packet_pool.recycle(attr.packet),
+                Ok(_) => {}
+                Err(e) => res = res.and(Err(e)),
+            }
+            // Close the owned timer after the packet is cancelled.
+            drop(attr.owned_timer);
+        }
+        res
+    }
+
+    /// Forgets the socket of a remove event if [`Poller::set_auto_cleanup`],
+    /// or only releases its owner with the feature `socket-owners`.
+    ///
+    /// A remove event of a timed out removal may arrive late, after the key is
+    /// reused. The socket is only forgotten if it has been closed, and the
+    /// registry is locked for writing only then.
+    fn process_socket_event(&self, event: &Event) {
+        // The socket events don't carry an `OVERLAPPED`.
+        if !event.0.lpOverlapped.is_null() || !event.is_removed() {
+            return;
+        }
+        let auto_cleanup = self.auto_cleanup.load(Ordering::Relaxed);
+        if !auto_cleanup && !cfg!(feature = "socket-owners") {
+            return;
+        }
+        let key = event.key();
+        let registry = self.registry.read();
+        let socket = match registry.socket_keys.as_ref() {
+            Some(keys) => keys.get(&key).copied(),
+            None => {
+                let mut sockets = registry
+                    .sources
+                    .iter()
+                    .filter(|(_, attr)| attr.key == key)
+                    .map(|(socket, _)| *socket);
+                match (sockets.next(), sockets.next()) {
+                    (Some(socket), None) => Some(socket),
+                    _ => None,
+                }
+            }
+        };
+        drop(registry);
+        let Some(socket) = socket else {
+            return;
+        };
+        if is_socket(socket) {
+            return;
+        }
+        if !auto_cleanup {
+            // The handle could be reused by a new socket, which may be added
+            // to another poller.
+            owners::release(socket, self.owner());
+            return;
+        }
+        let mut registry = self.registry.write();
+        // The socket may have been deleted or modified meanwhile.
+        if registry
+            .sources
+            .get(&socket)
+            .is_some_and(|attr| attr.key == key)
+        {
+            self.forget_socket(&mut registry, socket);
+        }
+    }
+
+    /// Splits the events of the sockets added by [`Poller::add_split`].
+    /// Returns the number of the events after splitting.
+    fn split_events(&self, events: &mut [MaybeUninit<Event>], len: usize) -> usize {
+        let registry = self.registry.read();
+        let mut total = len;
+        for i in 0..len {
+            let event = unsafe { events[i].assume_init_mut() };
+            // The socket events don't carry an `OVERLAPPED`.
+            if !event.0.lpOverlapped.is_null() {
+                continue;
+            }
+            let Some(&write_key) = registry.splits.get(&event.key()) else {
+                continue;
+            };
+            let bits = event.0.dwNumberOfBytesTransferred;
+            if bits & SOCK_NOTIFY_EVENT_OUT == 0 {
+                continue;
+            }
+            let write = OVERLAPPED_ENTRY {
+                lpCompletionKey: write_key,
+                dwNumberOfBytesTransferred: SOCK_NOTIFY_EVENT_OUT,
+                ..event.0
+            };
+            let rest = bits & !SOCK_NOTIFY_EVENT_OUT;
+            if rest == 0 {
+                event.0 = write;
+            } else {
+                event.0.dwNumberOfBytesTransferred = rest;
+                if total < events.len() {
+                    events[total] = MaybeUninit::new(Event(write));
+                    total += 1;
+                } else {
+                    // The writable part is lost if it fails, the same as an
+                    // edge missed.
+                    self.repost(write).ok();
+                }
+            }
+        }
+        total
+    }
+
+    /// Process a received event of waitable. A level-triggered waitable is
+    /// associated again, and the other packet of a waitable with timeout is
+    /// cancelled.
+    fn process_waitable_event(&self, event: &Event) {
+        // The waitable handle is passed as the APC context.
+        let handle = event.0.lpOverlapped as HANDLE;
+        if handle.is_null() {
+            return;
+        }
+        let registry = self.registry.read();
+        let Some(attr) = registry.waitables.get(&handle) else {
+            return;
+        };
+        if attr.key != event.key() {
+            return;
+        }
+        // The event has been received anyway. If these fail, the waitable is
+        // just not triggered again, or triggered one more time.
+        if let Some(timeout) = &attr.timeout {
+            if event.is_timeout() {
+                attr.packet.cancel().ok();
+            } else {
+                timeout.cancel().ok();
+            }
+        } else if attr.mode == PollMode::Level {
+            let res = attr.packet.associate_with_status(
+                self.port.as_raw_handle(),
+                handle,
+                handle,
+                attr.key,
+                attr.status,
+                attr.events as _,
+            );
+            attr.armed.store(res.is_ok(), Ordering::Relaxed);
+        } else {
+            attr.armed.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Delete a waitable from the poller.
+    ///
+    /// The event already queued to the port is removed, so that it is not
+    /// received after deletion. The timers added by [`Poller::add_timer`] are
+    /// closed.
+    pub fn delete_waitable(&self, waitable: HANDLE) -> Result<()> {
+        self.delete_waitable_locked(&mut self.registry.write(), waitable)
+    }
+
+    fn delete_waitable_locked(&self, registry: &mut Registry, waitable: HANDLE) -> Result<()> {
+        let WaitableAttr {
+            key,
+            packet,
+            timeout,
+            owned_timer,
+            ..
+        } = registry
+            .waitables
+            .remove(&waitable)
+            .ok_or(Error::new(ERROR_NOT_FOUND).with_context("delete waitable"))?;
+        registry.release_key(key);
+
+        // Remove the queued completion, so that no event of the deleted
+        // waitable is received later.
+        if let Some(timeout) = timeout
+            && timeout.cancel_and_remove()?.is_reusable()
+        {
+            registry.packet_pool.recycle(timeout.packet);
+        }
+        if packet.cancel_and_remove()?.is_reusable() {
+            registry.packet_pool.recycle(packet);
+        }
+        // Close the owned timer after the packet is cancelled.
+        drop(owned_timer);
+        Ok(())
+    }
+
+    /// Associates a file handle, e.g., a file or a named pipe opened for
+    /// overlapped I/O, with the port.
+    ///
+    /// The completions of the overlapped I/O on the handle are received by
+    /// [`Poller::wait`] with the key, and `lpOverlapped` of the event points
+    /// to the `OVERLAPPED` of the operation.
+    pub fn add_handle(&self, handle: HANDLE, key: usize) -> Result<()> {
+        check_key(key)?;
+        let mut registry = self.registry.write();
+        if registry.handles.contains_key(&handle) {
+            return Err(Error::new(ERROR_ALREADY_EXISTS).with_context("add handle"));
+        }
+        if registry.handles.is_full() {
+            return Err(Error::new(ERROR_NOT_ENOUGH_QUOTA));
+        }
+        registry.check_unique_key(key)?;
+        registry
+            .handles
+            .try_reserve(1)
+            .map_err(map_try_reserve_error)?;
+
+        associate(self.port.as_raw_handle(), handle, key)?;
+        // The room has been reserved.
+        registry.handles.try_insert(handle, key).ok();
+        registry.claim_key(key);
+        self.stats.add(Counter::Registrations, 1);
+        Ok(())
+    }
+
+    /// Disassociates a file handle from the port.
+    ///
+    /// The completions already queued are not purged.
+    pub fn delete_handle(&self, handle: HANDLE) -> Result<()> {
+        let mut registry = self.registry.write();
+        let key = registry
+            .handles
+            .remove(&handle)
+            .ok_or(Error::new(ERROR_NOT_FOUND).with_context("delete handle"))?;
+        registry.release_key(key);
+        disassociate(handle)
+    }
+
+    /// Waits for I/O events with an optional timeout.
+    ///
+    /// If the port is closed while waiting, it returns an error with
+    /// `ERROR_ABANDONED_WAIT_0`, see [`Error::is_abandoned`]. The event loop
+    /// should exit instead of waiting again.
+    ///
+    /// At most `u32::MAX` events are received in one wait, even if `events`
+    /// is longer.
+    pub fn wait(
+        &self,
+        events: &mut [MaybeUninit<Event>],
+        timeout: Option<Duration>,
+        alertable: bool,
+    ) -> Result<usize> {
+        self.wait_ex(events, timeout, alertable)
+            .map(|outcome| outcome.len())
+    }
+
+    /// Checks the readiness of a socket not registered, with an optional
+    /// timeout.
+    ///
+    /// The socket is added in oneshot mode with an internal key, waited for,
+    /// and deleted. Returns the received event with the raw socket as the
+    /// key, or an event with no flags if timed out. If the poller is shut down
+    /// meanwhile, the shutdown event is returned instead. The entries of
+    /// others received meanwhile are posted back to the port at last, and it
+    /// fails with `ERROR_NOT_ENOUGH_QUOTA` if more than 1024 are received.
+    ///
+    /// Every call registers and removes the socket, and waits for the removal.
+    /// It is convenient for probing a single socket, but should not be used in
+    /// an event loop. Only one call is allowed at a time on the pollers of the
+    /// same port, and the others fail with `ERROR_BUSY`.
+    pub fn poll_once(
+        &self,
+        socket: SOCKET,
+        interest: Interest,
+        timeout: Option<Duration>,
+    ) -> Result<Event> {
+        if self.polling_once.swap(true, Ordering::Acquire) {
+            return Err(Error::new(ERROR_BUSY).with_context("poll once"));
+        }
+        let res = self.poll_once_registered(socket, interest, timeout);
+        self.polling_once.store(false, Ordering::Release);
+        res
+    }
+
+    fn poll_once_registered(
+        &self,
+        socket: SOCKET,
+        interest: Interest,
+        timeout: Option<Duration>,
+    ) -> Result<Event> {
+        self.add_locked(
+            &mut self.registry.write(),
+            socket,
+            POLL_ONCE_KEY,
+            interest,
+            PollMode::Oneshot,
+        )?;
+        let mut foreign = Vec::new();
+        let res = self.poll_once_buffered(socket, timeout, &mut foreign);
+        let deleted = self.delete(socket);
+        // Try to repost all entries even if some of them fail.
+        let reposted = foreign
+            .into_iter()
+            .map(|entry| self.repost(entry))
+            .fold(Ok(()), Result::and);
+        res.and_then(|event| deleted.and(reposted).map(|_| event))
+    }
+
+    /// Receives the entries directly, so that the others are buffered without
+    /// being processed by [`Poller::wait`], and processed when reposted.
+    fn poll_once_buffered(
+        &self,
+        socket: SOCKET,
+        timeout: Option<Duration>,
+        foreign: &mut Vec<OVERLAPPED_ENTRY>,
+    ) -> Result<Event> {
+        const MAX_FOREIGN: usize = 1024;
+
+        let timeout = timeout.map(|dur| relative_timeout(dur, Rounding::Up));
+        let start = unsafe { GetTickCount64() };
+        let mut retry = false;
+        let mut entry = MaybeUninit::<Event>::uninit();
+        loop {
+            let mut timeout = match timeout {
+                Some(timeout) if retry => Some(remaining_timeout(timeout, start)),
+                timeout => timeout,
+            };
+            retry = true;
+            let mut received = 0;
+            let res = unsafe {
+                NtRemoveIoCompletionEx(
+                    self.port.as_raw_handle(),
+                    entry.as_mut_ptr().cast(),
+                    1,
+                    &mut received,
+                    timeout.as_mut(),
+                    0,
+                )
+            };
+            match res {
+                STATUS_SUCCESS => {}
+                STATUS_TIMEOUT => return Ok(Event::none(socket)),
+                STATUS_ABANDONED_WAIT_0 => {
+                    return Err(Error::new(ERROR_ABANDONED_WAIT_0).with_context(WAIT));
+                }
+                _ => {
+                    return Err(
+                        Error::new(unsafe { RtlNtStatusToDosError(res) }).with_context(WAIT)
+                    );
+                }
+            }
+            let mut event = unsafe { entry.assume_init() };
+            // Only the entries internal to the backend are processed here.
+            if !self.backend.process(&mut event.0) {
+                continue;
+            }
+            if event.key() == POLL_ONCE_KEY && event.0.lpOverlapped.is_null() {
+                event.0.lpCompletionKey = socket;
+                return Ok(event);
+            }
+            if foreign.len() >= MAX_FOREIGN {
+                // Post it back now, as the buffered ones are later.
+                self.repost(event.0)?;
+                return Err(Error::new(ERROR_NOT_ENOUGH_QUOTA).with_context("poll once"));
+            }
+            foreign
+                .try_reserve(1)
+                .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+            foreign.push(event.0);
+            if event.is_shutdown() {
+                // It is posted back with the others.
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Waits for I/O events with an optional timeout, alertable as set by
+    /// [`Poller::set_alertable`].
+    pub fn poll(
+        &self,
+        events: &mut [MaybeUninit<Event>],
+        timeout: Option<Duration>,
+    ) -> Result<usize> {
+        self.wait(events, timeout, self.alertable.load(Ordering::Relaxed))
+    }
+
+    /// Waits for I/O events with an optional timeout, and reports whether it
+    /// timed out or was interrupted by a user APC when no event is received.
+    pub fn wait_ex(
+        &self,
+        events: &mut [MaybeUninit<Event>],
+        timeout: Option<Duration>,
+        alertable: bool,
+    ) -> Result<WaitOutcome> {
+        let timeout = timeout.map(|dur| relative_timeout(dur, Rounding::Up));
+        self.wait_raw(events, timeout, alertable)
+    }
+
+    /// Waits for I/O events with an optional timeout, which is rounded to the
+    /// 100ns ticks as specified.
+    ///
+    /// [`Poller::wait`] always rounds up. [`Rounding::Down`] trades the
+    /// guarantee of never returning early for a lower wakeup latency.
+    pub fn wait_with_rounding(
+        &self,
+        events: &mut [MaybeUninit<Event>],
+        timeout: Option<Duration>,
+        alertable: bool,
+        rounding: Rounding,
+    ) -> Result<usize> {
+        let timeout = timeout.map(|dur| relative_timeout(dur, rounding));
+        self.wait_raw(events, timeout, alertable)
+            .map(|outcome| outcome.len())
+    }
+
+    /// Waits for I/O events until an absolute system time, in UTC.
+    ///
+    /// Unlike a relative timeout, the deadline doesn't drift across many
+    /// short waits. A deadline already passed doesn't block. It returns
+    /// `ERROR_INVALID_PARAMETER` if the deadline is beyond the range of the
+    /// NT timeout.
+    pub fn wait_until_system_time(
+        &self,
+        events: &mut [MaybeUninit<Event>],
+        deadline: FILETIME,
+        alertable: bool,
+    ) -> Result<usize> {
+        let deadline = ((deadline.dwHighDateTime as u64) << 32) | deadline.dwLowDateTime as u64;
+        // Positive value means absolute time, and zero means no wait.
+        if i64::try_from(deadline).is_err() {
+            return Err(Error::new(ERROR_INVALID_PARAMETER));
+        }
+        self.wait_raw(events, Some(deadline), alertable)
+            .map(|outcome| outcome.len())
+    }
+
+    /// Waits with the raw NT timeout, in 100ns ticks.
+    fn wait_raw(
+        &self,
+        events: &mut [MaybeUninit<Event>],
+        timeout: Option<u64>,
+        alertable: bool,
+    ) -> Result<WaitOutcome> {
+        self.stats.add(Counter::Waits, 1);
+        // The count is `u32`, and the rest of a longer buffer is left
+        // untouched.
+        let capacity = u32::try_from(events.len()).unwrap_or(u32::MAX);
+        let start = unsafe { GetTickCount64() };
+        let mut retry = false;
+        loop {
+            // The timeout is passed as is at first, so that it is precise.
+            let mut timeout = match timeout {
+                Some(timeout) if retry => Some(remaining_timeout(timeout, start)),
+                timeout => timeout,
+            };
+            let mut received = 0;
+            let res = unsafe {
+                NtRemoveIoCompletionEx(
+                    self.port.as_raw_handle(),
+                    events.as_mut_ptr().cast(),
+                    capacity,
+                    &mut received,
+                    timeout.as_mut(),
+                    alertable as _,
+                )
+            };
+            return match res {
+                STATUS_SUCCESS => {
+                    let mut len = self.process_events(events, received as usize);
+                    if len == 0 {
+                        // Only the entries internal to the backend are
+                        // received, wait again for the rest of the timeout.
+                        retry = true;
+                        continue;
+                    }
+                    if self.splitting.load(Ordering::Relaxed) {
+                        len = self.split_events(events, len);
+                    }
+                    if self.fairness.load(Ordering::Relaxed) == Fairness::RoundRobin as u8
+                        && len > 1
+                    {
+                        let rotation = self.rotation.fetch_add(1, Ordering::Relaxed);
+                        events[..len].rotate_left(rotation % len);
+                    }
+                    self.stats.add(Counter::Events, len as _);
+                    Ok(WaitOutcome::Events(len))
+                }
+                STATUS_TIMEOUT => Ok(WaitOutcome::TimedOut),
+                STATUS_USER_APC => Ok(WaitOutcome::Interrupted),
+                STATUS_ABANDONED_WAIT_0 => {
+                    Err(Error::new(ERROR_ABANDONED_WAIT_0).with_context(WAIT))
+                }
+                _ => Err(Error::new(unsafe { RtlNtStatusToDosError(res) }).with_context(WAIT)),
+            };
+        }
+    }
+
+    /// Processes the received entries. The entries internal to the backend
+    /// are dropped, and the others are moved forward. Returns the number of
+    /// the events left.
+    fn process_events(&self, events: &mut [MaybeUninit<Event>], received: usize) -> usize {
+        let mut len = 0;
+        for i in 0..received {
+            let event = unsafe { events[i].assume_init_mut() };
+            if event.is_shutdown() {
+                // Pass it on to the other waits. If it fails, they are not
+                // woken up, and it could be shut down again.
+                self.repost(event.0).ok();
+            } else if event.is_notify() {
+                self.notified.store(false, Ordering::Release);
+            } else if self.backend.process(&mut event.0) {
+                self.process_waitable_event(event);
+                self.process_socket_event(event);
+            } else {
+                continue;
+            }
+            events[len] = events[i];
+            len += 1;
         }
-        self.sources
-            .try_insert(socket, interest.key())
-            .map_err(map_try_reserve_error)?;
-
-        let info = create_registration(socket, interest, mode, true);
-        self.update_source(info)
+        len
     }
 
-    /// Modifies an existing socket.
-    pub fn modify(&self, socket: SOCKET, interest: Event, mode: PollMode) -> Result<()> {
-        let oldkey = self.sources.get(&socket).ok_or(Error(ERROR_NOT_FOUND))?;
+    /// Waits for I/O events with an optional timeout, and merges the
+    /// readiness events of the same key into one.
+    ///
+    /// Only the events without `lpOverlapped` and with no flag other than
+    /// [`Interest::ALL`] are merged, e.g., the socket events and the ones
+    /// posted by [`Poller::post`]. Their flags are ORed together, so fewer
+    /// events than received may be returned. The merged event is placed at
+    /// the first occurrence of the key. The other events, e.g., notifications,
+    /// remove events, completions and the ones posted by
+    /// [`Poller::post_bytes`], pass through unchanged, and the order is kept.
+    ///
+    /// The merged events are looked up by scanning the returned ones, without
+    /// allocation. The cost grows quadratically with the size of the buffer.
+    pub fn wait_coalesced(
+        &self,
+        events: &mut [MaybeUninit<Event>],
+        timeout: Option<Duration>,
+        alertable: bool,
+    ) -> Result<usize> {
+        fn is_readiness(event: &Event) -> bool {
+            event.0.lpOverlapped.is_null()
+                && !event.is_notify()
+                && event.events() & !Interest::ALL.bits() == 0
+        }
 
-        if oldkey != &interest.key() {
-            // To change the key, remove the old registration and wait for REMOVE event.
-            let info = create_registration(socket, Event::none(*oldkey), PollMode::Oneshot, false);
-            self.update_and_wait_for_remove(info, *oldkey)?;
+        let received = self.wait(events, timeout, alertable)?;
+        let mut len = 0;
+        for i in 0..received {
+            let event = unsafe { events[i].assume_init() };
+            if is_readiness(&event)
+                && let Some(merged) = events[..len]
+                    .iter_mut()
+                    .map(|merged| unsafe { merged.assume_init_mut() })
+                    .find(|merged| merged.key() == event.key() && is_readiness(merged))
+            {
+                merged.0.dwNumberOfBytesTransferred |= event.events();
+                continue;
+            }
+            events[len].write(event);
+            len += 1;
         }
-        let info = create_registration(socket, interest, mode, true);
-        self.update_source(info)
+        Ok(len)
     }
 
-    /// Deletes a socket.
-    pub fn delete(&mut self, socket: SOCKET) -> Result<()> {
-        let key = self.sources.remove(&socket).ok_or(Error(ERROR_NOT_FOUND))?;
-        let info = create_registration(socket, Event::none(key), PollMode::Oneshot, false);
-        self.update_and_wait_for_remove(info, key)
+    /// Waits for I/O events with an optional timeout into an initialized
+    /// buffer.
+    pub fn wait_init(
+        &self,
+        events: &mut [Event],
+        timeout: Option<Duration>,
+        alertable: bool,
+    ) -> Result<usize> {
+        // SAFETY: only complete entries are written to the buffer.
+        let events = unsafe { &mut *(events as *mut [Event] as *mut [MaybeUninit<Event>]) };
+        self.wait(events, timeout, alertable)
     }
 
-    /// Add a new waitable to the poller.
-    pub fn add_waitable(&mut self, handle: HANDLE, interest: Event) -> Result<()> {
-        let key = interest.key();
-        if self.waitables.contains_key(&handle) {
-            return Err(Error(ERROR_ALREADY_EXISTS));
-        }
+    /// Waits for I/O events with an optional timeout, and iterates the
+    /// received events.
+    pub fn wait_iter<'a>(
+        &self,
+        events: &'a mut [MaybeUninit<Event>],
+        timeout: Option<Duration>,
+        alertable: bool,
+    ) -> Result<impl Iterator<Item = &'a Event> + use<'a>> {
+        let len = self.wait(events, timeout, alertable)?;
+        Ok(events[..len].iter().map(|e| unsafe { e.assume_init_ref() }))
+    }
 
-        let mut packet = wait::WaitCompletionPacket::new()?;
-        packet.associate(
-            self.port.as_raw_handle(),
-            handle,
-            key,
-            interest_to_events(&interest) as _,
+    /// Waits for I/O events with an optional timeout into an [`Events`]
+    /// buffer.
+    ///
+    /// The previously received events are cleared.
+    pub fn wait_events(&self, events: &mut Events, timeout: Option<Duration>) -> Result<usize> {
+        let events = events.as_mut_vec();
+        events.clear();
+        let len = self.wait(
+            events.spare_capacity_mut(),
+            timeout,
+            self.alertable.load(Ordering::Relaxed),
         )?;
-        self.waitables
-            .try_insert(handle, WaitableAttr { key, packet })
-            .map_err(map_try_reserve_error)?;
-        Ok(())
+        unsafe { events.set_len(len) };
+        Ok(len)
     }
 
-    /// Update a waitable in the poller.
-    pub fn modify_waitable(&mut self, waitable: HANDLE, interest: Event) -> Result<()> {
-        let WaitableAttr { key, packet } = self
-            .waitables
-            .get_mut(&waitable)
-            .ok_or(Error(ERROR_NOT_FOUND))?;
-
-        let cancelled = packet.cancel()?;
-        if !cancelled {
-            // The packet could not be reused, create a new one.
-            *packet = WaitCompletionPacket::new()?;
+    /// Waits for I/O events with an optional timeout into the free slots of
+    /// an [`EventRing`].
+    ///
+    /// If the free slots wrap around, and the first part is filled without a
+    /// shutdown event, the second part is filled without waiting. It doesn't
+    /// block if the ring is full. Returns the number of received events.
+    pub fn wait_into_ring(&self, ring: &mut EventRing, timeout: Option<Duration>) -> Result<usize> {
+        let (first, second) = ring.spare_slots();
+        if first.is_empty() {
+            return Ok(0);
         }
-        packet.associate(
-            self.port.as_raw_handle(),
-            waitable,
-            *key,
-            interest_to_events(&interest) as _,
-        )
+        let mut len = self.wait(first, timeout, self.alertable.load(Ordering::Relaxed))?;
+        if len == first.len()
+            && !second.is_empty()
+            && !first
+                .iter()
+                .any(|event| unsafe { event.assume_init_ref() }.is_shutdown())
+            // The events in the first part are received anyway, so the error
+            // of the second wait is ignored.
+            && let Ok(more) = self.wait(second, Some(Duration::ZERO), false)
+        {
+            len += more;
+        }
+        unsafe { ring.commit(len) };
+        Ok(len)
     }
 
-    /// Delete a waitable from the poller.
-    pub fn delete_waitable(&mut self, waitable: HANDLE) -> Result<()> {
-        let WaitableAttr { mut packet, .. } = self
-            .waitables
-            .remove(&waitable)
-            .ok_or(Error(ERROR_NOT_FOUND))?;
+    /// Receives all ready events without waiting, and appends them to
+    /// `sink`.
+    ///
+    /// It stops when fewer events than asked are ready, or the shutdown event
+    /// is received. The level-triggered sources are ready again right after
+    /// being received, so at most 1024 events are received by a call. Returns
+    /// the number of received events.
+    pub fn drain(&self, sink: &mut Vec<Event>) -> Result<usize> {
+        const CHUNK: usize = 64;
+        const MAX_CHUNKS: usize = 16;
 
-        packet.cancel()?;
-        Ok(())
+        let mut total = 0;
+        for _ in 0..MAX_CHUNKS {
+            sink.try_reserve(CHUNK)
+                .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+            let len = self.wait(
+                &mut sink.spare_capacity_mut()[..CHUNK],
+                Some(Duration::ZERO),
+                false,
+            )?;
+            let start = sink.len();
+            unsafe { sink.set_len(start + len) };
+            total += len;
+            if len < CHUNK || sink[start..].iter().any(Event::is_shutdown) {
+                break;
+            }
+        }
+        Ok(total)
     }
 
-    /// Add or modify the registration.
-    fn update_source(&self, mut reg: SOCK_NOTIFY_REGISTRATION) -> Result<()> {
-        let res = unsafe {
-            ProcessSocketNotifications(
-                self.port.as_raw_handle(),
-                1,
-                &mut reg,
-                0,
-                0,
-                null_mut(),
-                null_mut(),
-            )
-        };
-        if res == ERROR_SUCCESS {
-            if reg.registrationResult == ERROR_SUCCESS {
-                Ok(())
+    /// Waits for I/O events with an optional timeout, and calls `f` with at
+    /// most `max` received events.
+    ///
+    /// The events are received into a buffer on the stack in chunks. Only the
+    /// first chunk waits, and the following ones are received without
+    /// waiting, as long as the previous chunk is filled and has no shutdown
+    /// event. Returns the number of the events passed to `f`.
+    ///
+    /// `f` may wait on the same poller. The events received by the inner wait
+    /// are not passed to `f`.
+    pub fn wait_for_each(
+        &self,
+        max: usize,
+        timeout: Option<Duration>,
+        alertable: bool,
+        mut f: impl FnMut(&Event),
+    ) -> Result<usize> {
+        const CHUNK: usize = 64;
+
+        let mut events = [MaybeUninit::<Event>::uninit(); CHUNK];
+        let mut total = 0;
+        while total < max {
+            let chunk = &mut events[..(max - total).min(CHUNK)];
+            let len = if total == 0 {
+                self.wait(chunk, timeout, alertable)?
             } else {
-                Err(Error(reg.registrationResult))
+                // The events before are passed to `f` anyway, so the error of
+                // the following waits is ignored.
+                match self.wait(chunk, Some(Duration::ZERO), false) {
+                    Ok(len) => len,
+                    Err(_) => break,
+                }
+            };
+            let mut shutdown = false;
+            for event in &chunk[..len] {
+                let event = unsafe { event.assume_init_ref() };
+                shutdown |= event.is_shutdown();
+                f(event);
+            }
+            total += len;
+            if shutdown || len < chunk.len() {
+                break;
             }
-        } else {
-            Err(Error(res))
         }
+        Ok(total)
     }
 
-    /// Attempt to remove a registration, and wait for the
-    /// `SOCK_NOTIFY_EVENT_REMOVE` event.
-    fn update_and_wait_for_remove(
+    /// Waits for I/O events until an optional deadline.
+    ///
+    /// If the deadline has passed, it doesn't block.
+    #[cfg(feature = "std")]
+    pub fn wait_deadline(
         &self,
-        mut reg: SOCK_NOTIFY_REGISTRATION,
-        key: usize,
-    ) -> Result<()> {
-        debug_assert_eq!(reg.operation, SOCK_NOTIFY_OP_REMOVE as _);
-        let mut received = 0;
-        let mut entry: MaybeUninit<OVERLAPPED_ENTRY> = MaybeUninit::uninit();
+        events: &mut [MaybeUninit<Event>],
+        deadline: Option<std::time::Instant>,
+        alertable: bool,
+    ) -> Result<usize> {
+        let timeout =
+            deadline.map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()));
+        self.wait(events, timeout, alertable)
+    }
 
-        let repost = |entry: OVERLAPPED_ENTRY| {
-            self.post_raw(
-                entry.dwNumberOfBytesTransferred,
-                entry.lpCompletionKey,
-                entry.lpOverlapped,
-            )
-        };
+    /// Waits for I/O events with an optional timeout, and reports whether
+    /// there are more events than `events` could hold.
+    ///
+    /// When `events` is filled, the port is probed without waiting. The probed
+    /// entry, if any, is posted back to the queue.
+    pub fn wait_with_overflow(
+        &self,
+        events: &mut [MaybeUninit<Event>],
+        timeout: Option<Duration>,
+        alertable: bool,
+    ) -> Result<(usize, bool)> {
+        let len = self.wait(events, timeout, alertable)?;
+        if len == 0 || len < events.len() {
+            return Ok((len, false));
+        }
+        Ok((len, self.has_pending()?))
+    }
 
-        // Update the registration and wait for the event in the same time.
-        // However, the returned completion entry may not be the wanted REMOVE event.
+    /// Whether any entry is queued, probed without waiting.
+    ///
+    /// The probed entry, if any, is posted back to the end of the queue.
+    pub fn has_pending(&self) -> Result<bool> {
+        let mut entry: MaybeUninit<OVERLAPPED_ENTRY> = MaybeUninit::uninit();
+        let mut received = 0;
         let res = unsafe {
-            ProcessSocketNotifications(
+            NtRemoveIoCompletionEx(
                 self.port.as_raw_handle(),
+                &mut entry,
                 1,
-                &mut reg,
-                0,
-                1,
-                entry.as_mut_ptr().cast(),
                 &mut received,
+                Some(&mut 0),
+                0,
             )
         };
         match res {
-            ERROR_SUCCESS | WAIT_TIMEOUT => {
-                if reg.registrationResult != ERROR_SUCCESS {
-                    // If the registration is not successful, the received entry should be reposted.
-                    if received == 1 {
-                        repost(unsafe { entry.assume_init() })?;
-                    }
-                    return Err(Error(reg.registrationResult));
-                }
-            }
-            _ => return Err(Error(res)),
-        }
-        if received == 1 {
-            // The registration is successful, and check the received entry.
-            let entry = unsafe { entry.assume_init() };
-            if entry.lpCompletionKey == key {
-                // If the entry is current key but not the remove event, just ignore it.
-                if (entry.dwNumberOfBytesTransferred & SOCK_NOTIFY_EVENT_REMOVE) != 0 {
-                    return Ok(());
-                }
-            } else {
-                repost(entry)?;
+            STATUS_SUCCESS => {
+                debug_assert_eq!(received, 1);
+                self.repost(unsafe { entry.assume_init() })?;
+                Ok(true)
             }
+            STATUS_TIMEOUT => Ok(false),
+            STATUS_ABANDONED_WAIT_0 => Err(Error::new(ERROR_ABANDONED_WAIT_0).with_context(WAIT)),
+            _ => Err(Error::new(unsafe { RtlNtStatusToDosError(res) }).with_context(WAIT)),
         }
+    }
+
+    /// Discards the queued events with the key, e.g., of a socket just
+    /// deleted, before the key is reused.
+    ///
+    /// The queue is drained without waiting, and the other entries are posted
+    /// back in their original order at last. Returns the number of the
+    /// discarded events. The events received by other waits in the meantime
+    /// are not discarded.
+    pub fn flush_pending(&self, key: usize) -> Result<usize> {
+        check_key(key)?;
+        let mut foreign = Vec::new();
+        let res = self.flush_pending_buffered(key, &mut foreign);
+        // Try to repost all entries even if some of them fail.
+        let reposted = foreign
+            .into_iter()
+            .map(|entry| self.repost(entry))
+            .fold(Ok(()), Result::and);
+        res.and_then(|discarded| reposted.map(|_| discarded))
+    }
 
-        // No wanted event, start a loop to wait for it.
-        // TODO: any better solutions?
+    fn flush_pending_buffered(
+        &self,
+        key: usize,
+        foreign: &mut Vec<OVERLAPPED_ENTRY>,
+    ) -> Result<usize> {
+        const CHUNK: usize = 64;
+
+        let mut entries = [MaybeUninit::<OVERLAPPED_ENTRY>::uninit(); CHUNK];
+        let mut discarded = 0;
         loop {
+            // Reserve the room before receiving, so that no entry is lost.
+            foreign
+                .try_reserve(CHUNK)
+                .map_err(|_| Error::new(ERROR_NOT_ENOUGH_MEMORY))?;
+            let mut received = 0;
             let res = unsafe {
-                ProcessSocketNotifications(
+                NtRemoveIoCompletionEx(
                     self.port.as_raw_handle(),
-                    0,
-                    null_mut(),
-                    0,
-                    1,
-                    entry.as_mut_ptr().cast(),
+                    entries.as_mut_ptr().cast(),
+                    CHUNK as _,
                     &mut received,
+                    Some(&mut 0),
+                    0,
                 )
             };
             match res {
-                ERROR_SUCCESS => {
-                    debug_assert_eq!(received, 1);
-                    let entry = unsafe { entry.assume_init() };
-                    if entry.lpCompletionKey == key {
-                        if (entry.dwNumberOfBytesTransferred & SOCK_NOTIFY_EVENT_REMOVE) != 0 {
-                            return Ok(());
-                        }
-                    } else {
-                        repost(entry)?;
-                    }
+                STATUS_SUCCESS => {}
+                STATUS_TIMEOUT => return Ok(discarded),
+                STATUS_ABANDONED_WAIT_0 => {
+                    return Err(Error::new(ERROR_ABANDONED_WAIT_0).with_context(WAIT));
+                }
+                _ => {
+                    return Err(
+                        Error::new(unsafe { RtlNtStatusToDosError(res) }).with_context(WAIT)
+                    );
+                }
+            }
+            for entry in &entries[..received as usize] {
+                let entry = unsafe { entry.assume_init() };
+                if entry.lpCompletionKey == key {
+                    discarded += 1;
+                } else {
+                    foreign.push(entry);
                 }
-                WAIT_TIMEOUT => {}
-                _ => return Err(Error(res)),
+            }
+            if (received as usize) < CHUNK {
+                return Ok(discarded);
             }
         }
     }
 
-    /// Waits for I/O events with an optional timeout.
-    pub fn wait(
-        &self,
-        events: &mut [MaybeUninit<Event>],
-        timeout: Option<Duration>,
-        alertable: bool,
-    ) -> Result<usize> {
-        #[link(name = "ntdll")]
-        unsafe extern "system" {
-            fn NtRemoveIoCompletionEx(
-                handle: HANDLE,
-                information: *mut MaybeUninit<OVERLAPPED_ENTRY>,
-                count: u32,
-                removed: *mut u32,
-                timeout: Option<&mut u64>,
-                alertable: BOOLEAN,
-            ) -> NTSTATUS;
-        }
-
-        let mut timeout: Option<u64> = timeout.and_then(|dur| {
-            dur.as_secs()
-                .checked_mul(10_000_000)
-                .and_then(|ns| ns.checked_add(dur.subsec_nanos().div_ceil(100) as _))
-                .and_then(|ns| (ns as i64).checked_neg())
-                .map(|ns| ns as u64)
-        });
-        let mut received = 0;
-        let res = unsafe {
-            NtRemoveIoCompletionEx(
-                self.port.as_raw_handle(),
-                events.as_mut_ptr().cast(),
-                events.len() as _,
-                &mut received,
-                timeout.as_mut(),
-                alertable as _,
-            )
-        };
-        match res {
-            STATUS_SUCCESS => Ok(received as _),
-            STATUS_TIMEOUT | STATUS_USER_APC => Ok(0),
-            _ => Err(Error(unsafe { RtlNtStatusToDosError(res) })),
+    /// Wakes up a blocking [`Poller::wait`] with a notification event.
+    ///
+    /// Notifications are coalesced: only one notification event is queued
+    /// until it is received. See [`Event::is_notify`].
+    pub fn notify(&self) -> Result<()> {
+        if !self.notified.swap(true, Ordering::AcqRel) {
+            self.post_raw(0, NOTIFY_KEY, null_mut())
+                .inspect_err(|_| self.notified.store(false, Ordering::Release))?;
         }
+        Ok(())
+    }
+
+    /// Wakes up all blocking waits on the port with a shutdown event, so that
+    /// the threads could exit. See [`Event::is_shutdown`].
+    ///
+    /// The number of the waiting threads is not tracked. Instead, the shutdown
+    /// event is posted once, and every wait receiving it posts it back to the
+    /// port. All the current and later waits, including the ones on the
+    /// cloned pollers, receive it, so a thread should stop waiting after
+    /// receiving it.
+    ///
+    /// The event is never consumed, so [`Poller::wait`] must not be called
+    /// again after receiving it, or it returns the event immediately and a
+    /// loop spins. The methods waiting more than once, e.g.,
+    /// [`Poller::drain`] and [`Poller::wait_for_each`], stop at it.
+    pub fn shutdown(&self) -> Result<()> {
+        self.post_raw(EVENT_SHUTDOWN, NOTIFY_KEY, null_mut())
+    }
+
+    /// Wakes up a blocking [`Poller::wait`] on the specified thread with a
+    /// user APC.
+    ///
+    /// It only works when the wait is `alertable`, and then the wait returns
+    /// no event. Unlike [`Poller::notify`], no packet is queued to the port.
+    pub fn cancel_wait(&self, thread: HANDLE) -> Result<()> {
+        waker::wake_thread(thread)
+    }
+
+    /// Creates a [`ThreadWaker`] to wake up the alertable waits of the
+    /// current thread from other threads.
+    pub fn waker_for_current_thread(&self) -> Result<ThreadWaker> {
+        ThreadWaker::current()
+    }
+
+    /// Push a received entry back into the queue.
+    fn repost(&self, entry: OVERLAPPED_ENTRY) -> Result<()> {
+        repost(self.port.as_raw_handle(), entry)
     }
 
     /// Push an IOCP packet into the queue.
     pub fn post(&self, event: Event) -> Result<()> {
+        check_key(event.key())?;
         self.post_raw(interest_to_events(&event), event.key(), null_mut())
     }
 
+    /// Push an IOCP packet with arbitrary bits into the queue.
+    ///
+    /// Unlike [`Poller::post`], the bits are not limited to the standard
+    /// event flags. The receiver reads them with [`Event::events`].
+    pub fn post_bytes(&self, key: usize, bytes: u32) -> Result<()> {
+        check_key(key)?;
+        self.post_raw(bytes, key, null_mut())
+    }
+
+    /// Push a custom IOCP packet into the queue.
+    ///
+    /// The received [`Event`] contains the `transferred` and `overlapped`
+    /// as is. It could be used to bridge the completions of overlapped I/O.
+    ///
+    /// # Safety
+    ///
+    /// The `overlapped` pointer is not accessed by the poller, but it should
+    /// be valid until the receiver of the event handles it. It should not be
+    /// a handle of any waitable registered with the same key.
+    pub unsafe fn post_custom(
+        &self,
+        transferred: u32,
+        key: usize,
+        overlapped: *mut OVERLAPPED,
+    ) -> Result<()> {
+        check_key(key)?;
+        self.post_raw(transferred, key, overlapped)
+    }
+
     fn post_raw(&self, transferred: u32, key: usize, overlapped: *mut OVERLAPPED) -> Result<()> {
-        let res = unsafe {
-            PostQueuedCompletionStatus(self.port.as_raw_handle(), transferred, key, overlapped)
-        };
-        if res == 0 {
-            Err(Error::last_os_error())
-        } else {
-            Ok(())
-        }
+        post_raw(self.port.as_raw_handle(), transferred, key, overlapped)
+    }
+}
+
+/// Push an IOCP packet into the queue of the port.
+fn post_raw(port: HANDLE, transferred: u32, key: usize, overlapped: *mut OVERLAPPED) -> Result<()> {
+    let res = unsafe { PostQueuedCompletionStatus(port, transferred, key, overlapped) };
+    if res == 0 {
+        Err(Error::last_os_error().with_context("PostQueuedCompletionStatus"))
+    } else {
+        Ok(())
+    }
+}
+
+/// `FILE_COMPLETION_INFORMATION`.
+#[repr(C)]
+struct FileCompletionInformation {
+    port: HANDLE,
+    key: usize,
+}
+
+/// `FileReplaceCompletionInformation` of `FILE_INFORMATION_CLASS`.
+const FILE_REPLACE_COMPLETION_INFORMATION: i32 = 61;
+
+/// Associates the file handle with the port.
+fn associate(port: HANDLE, handle: HANDLE, key: usize) -> Result<()> {
+    let res = unsafe { CreateIoCompletionPort(handle, port, key, 0) };
+    if res.is_null() {
+        Err(Error::last_os_error().with_context("CreateIoCompletionPort"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Removes the association of the file handle with any port.
+fn disassociate(handle: HANDLE) -> Result<()> {
+    let info = FileCompletionInformation {
+        port: null_mut(),
+        key: 0,
+    };
+    let mut iosb = MaybeUninit::<IO_STATUS_BLOCK>::uninit();
+    let status = unsafe {
+        NtSetInformationFile(
+            handle,
+            iosb.as_mut_ptr(),
+            (&raw const info).cast(),
+            size_of::<FileCompletionInformation>() as _,
+            FILE_REPLACE_COMPLETION_INFORMATION,
+        )
+    };
+    if status == STATUS_SUCCESS {
+        Ok(())
+    } else {
+        Err(Error::new(unsafe { RtlNtStatusToDosError(status) })
+            .with_context("NtSetInformationFile"))
+    }
+}
+
+/// Push a received entry back into the queue of the port.
+///
+/// Unlike `PostQueuedCompletionStatus`, the status in `Internal` is kept.
+fn repost(port: HANDLE, entry: OVERLAPPED_ENTRY) -> Result<()> {
+    let status = unsafe {
+        NtSetIoCompletion(
+            port,
+            entry.lpCompletionKey,
+            entry.lpOverlapped,
+            entry.Internal as NTSTATUS,
+            entry.dwNumberOfBytesTransferred as _,
+        )
+    };
+    if status == STATUS_SUCCESS {
+        Ok(())
+    } else {
+        Err(Error::new(unsafe { RtlNtStatusToDosError(status) }).with_context("NtSetIoCompletion"))
+    }
+}
+
+/// Converts the duration to a negative NT timeout, which is relative.
+///
+/// A duration too long is clamped to the longest relative timeout, instead
+/// of waiting infinitely.
+fn relative_timeout(dur: Duration, rounding: Rounding) -> u64 {
+    let nanos = dur.subsec_nanos();
+    let ticks = match rounding {
+        Rounding::Up => nanos.div_ceil(100),
+        Rounding::Down => nanos / 100,
+        Rounding::Nearest => (nanos + 50) / 100,
+    };
+    let ticks = dur
+        .as_secs()
+        .checked_mul(10_000_000)
+        .and_then(|ns| ns.checked_add(ticks as _))
+        .and_then(|ns| i64::try_from(ns).ok());
+    ticks.map_or(i64::MIN, |ns| -ns) as u64
+}
+
+/// The rest of a relative NT timeout since `start`, the tick count in
+/// milliseconds. The absolute and zero timeouts are kept as is.
+fn remaining_timeout(timeout: u64, start: u64) -> u64 {
+    let timeout = timeout as i64;
+    if timeout >= 0 {
+        return timeout as u64;
+    }
+    let elapsed = unsafe { GetTickCount64() }.saturating_sub(start);
+    let elapsed = i64::try_from(elapsed.saturating_mul(10_000)).unwrap_or(i64::MAX);
+    // Zero after the timeout elapsed, which doesn't block.
+    timeout.saturating_add(elapsed).min(0) as u64
+}
+
+/// The default max time to wait for the `SOCK_NOTIFY_EVENT_REMOVE` event.
+const DEFAULT_REMOVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The event flag of a timed out waitable.
+const EVENT_TIMEOUT: u32 = 1 << 16;
+
+/// The event flag of the notification posted by [`Poller::shutdown`].
+const EVENT_SHUTDOWN: u32 = 1 << 17;
+
+/// The context of the errors when waiting.
+const WAIT: &str = "NtRemoveIoCompletionEx";
+
+/// The completion key reserved for [`Poller::notify`].
+const NOTIFY_KEY: usize = usize::MAX;
+
+/// The completion key reserved for [`Poller::poll_once`].
+const POLL_ONCE_KEY: usize = usize::MAX - 1;
+
+/// Rejects the modes not supported by waitables.
+fn check_waitable_mode(mode: PollMode) -> Result<()> {
+    match mode {
+        PollMode::Oneshot | PollMode::Level => Ok(()),
+        _ => Err(Error::new(ERROR_NOT_SUPPORTED)),
+    }
+}
+
+/// Rejects the keys reserved for notifications and [`Poller::poll_once`].
+fn check_key(key: usize) -> Result<()> {
+    if key == NOTIFY_KEY || key == POLL_ONCE_KEY {
+        Err(Error::new(ERROR_INVALID_PARAMETER))
+    } else {
+        Ok(())
     }
 }
 
+/// Whether the handle is an open socket.
+fn is_socket(socket: SOCKET) -> bool {
+    // `getsockopt` only touches the buffers we own, and fails gracefully for
+    // handles that are not sockets, or are already closed.
+    let mut ty: c_int = 0;
+    let mut len = size_of::<c_int>() as c_int;
+    let res = unsafe { getsockopt(socket, SOL_SOCKET, SO_TYPE, (&raw mut ty).cast(), &mut len) };
+    res == 0
+        || !matches!(
+            unsafe { WSAGetLastError() },
+            WSAENOTSOCK | WSANOTINITIALISED
+        )
+}
+
 /// Indicates that a socket can read or write without blocking.
 #[derive(Clone, Copy)]
 #[repr(transparent)]
@@ -410,6 +2739,36 @@ impl Event {
         })
     }
 
+    /// Create an event with readable interest.
+    pub fn readable(key: usize) -> Self {
+        Self::none(key).with_readable(true)
+    }
+
+    /// Create an event with writable interest.
+    pub fn writable(key: usize) -> Self {
+        Self::none(key).with_writable(true)
+    }
+
+    /// Create an event with all interests: readable, writable, hangup and
+    /// error.
+    pub fn all(key: usize) -> Self {
+        Self::none(key)
+            .with_readable(true)
+            .with_writable(true)
+            .with_hangup(true)
+            .with_error(true)
+    }
+
+    /// Create an event from the raw completion entry.
+    pub const fn from_raw(entry: OVERLAPPED_ENTRY) -> Self {
+        Self(entry)
+    }
+
+    /// Get the raw completion entry.
+    pub const fn into_raw(self) -> OVERLAPPED_ENTRY {
+        self.0
+    }
+
     /// Key of the event.
     pub const fn key(&self) -> usize {
         self.0.lpCompletionKey
@@ -420,6 +2779,36 @@ impl Event {
         self.0.dwNumberOfBytesTransferred
     }
 
+    /// The readiness flags of the event. The flags other than readable,
+    /// writable, hangup and error are dropped.
+    pub const fn readiness(&self) -> Readiness {
+        Readiness::from_bits_truncate(self.events())
+    }
+
+    /// The socket of the event.
+    ///
+    /// See [`Poller::socket_for_key`].
+    pub fn socket(&self, poller: &Poller) -> Option<SOCKET> {
+        poller.socket_for_key(self.key())
+    }
+
+    /// Is a notification event posted by [`Poller::notify`].
+    ///
+    /// The key `usize::MAX` is reserved for notifications, and cannot be used
+    /// to register sockets or waitables. Neither can `usize::MAX - 1`, which
+    /// is reserved for [`Poller::poll_once`].
+    pub const fn is_notify(&self) -> bool {
+        self.key() == NOTIFY_KEY
+    }
+
+    /// Is a shutdown event posted by [`Poller::shutdown`]. The thread should
+    /// stop waiting.
+    ///
+    /// It is also a notification event, see [`Event::is_notify`].
+    pub fn is_shutdown(&self) -> bool {
+        self.is_notify() && self.get_event(EVENT_SHUTDOWN)
+    }
+
     fn set_event(&mut self, e: u32, value: bool) {
         if value {
             self.0.dwNumberOfBytesTransferred |= e;
@@ -495,77 +2884,91 @@ impl Event {
     pub fn is_error(&self) -> bool {
         self.get_event(SOCK_NOTIFY_EVENT_ERR)
     }
-}
 
-fn interest_to_filter(interest: &Event) -> u16 {
-    let mut filter = SOCK_NOTIFY_REGISTER_EVENT_NONE;
-    if interest.is_readable() {
-        filter |= SOCK_NOTIFY_REGISTER_EVENT_IN;
+    /// Is remove event, i.e., the registration of the socket has been torn
+    /// down, e.g., because the socket is closed. No more events of the
+    /// socket will be received.
+    pub fn is_removed(&self) -> bool {
+        self.get_event(SOCK_NOTIFY_EVENT_REMOVE)
     }
-    if interest.is_writable() {
-        filter |= SOCK_NOTIFY_REGISTER_EVENT_OUT;
+
+    /// Is read hangup event, i.e., the peer has shut down its sending side.
+    ///
+    /// `ProcessSocketNotifications` has only one hangup event for both
+    /// cases, so it is the same as [`Event::is_hangup`].
+    pub fn is_read_hangup(&self) -> bool {
+        self.is_hangup()
     }
-    if interest.is_hangup() {
-        filter |= SOCK_NOTIFY_REGISTER_EVENT_HANGUP;
+
+    /// Is timeout event of a waitable added by
+    /// [`Poller::add_waitable_timeout`].
+    pub fn is_timeout(&self) -> bool {
+        self.get_event(EVENT_TIMEOUT)
     }
-    filter as _
-}
 
-fn interest_to_events(interest: &Event) -> u32 {
-    let mut events = 0;
-    if interest.is_readable() {
-        events |= SOCK_NOTIFY_EVENT_IN;
+    /// The raw status of the completion entry, i.e., its `Internal` field.
+    ///
+    /// Its meaning depends on the origin of the event:
+    /// - For waitables, it is the status provided at registration by
+    ///   [`Poller::add_waitable_with_status`], or `STATUS_SUCCESS`.
+    /// - For sockets, it is set by the system and not documented.
+    /// - For the handles added by [`Poller::add_handle`], it is the status of
+    ///   the overlapped operation.
+    /// - For the posted events, it is always `STATUS_SUCCESS`.
+    pub fn status(&self) -> NTSTATUS {
+        self.0.Internal as NTSTATUS
     }
-    if interest.is_writable() {
-        events |= SOCK_NOTIFY_EVENT_OUT;
+}
+
+impl From<OVERLAPPED_ENTRY> for Event {
+    fn from(entry: OVERLAPPED_ENTRY) -> Self {
+        Self::from_raw(entry)
     }
-    if interest.is_hangup() {
-        events |= SOCK_NOTIFY_EVENT_HANGUP;
+}
+
+impl From<&OVERLAPPED_ENTRY> for Event {
+    fn from(entry: &OVERLAPPED_ENTRY) -> Self {
+        Self::from_raw(*entry)
     }
-    if interest.is_error() {
-        events |= SOCK_NOTIFY_EVENT_ERR;
+}
+
+impl From<Event> for OVERLAPPED_ENTRY {
+    fn from(event: Event) -> Self {
+        event.into_raw()
     }
-    events
 }
 
-fn mode_to_flags(mode: PollMode) -> u8 {
-    let flags = match mode {
-        PollMode::Oneshot => SOCK_NOTIFY_TRIGGER_ONESHOT | SOCK_NOTIFY_TRIGGER_LEVEL,
-        PollMode::Level => SOCK_NOTIFY_TRIGGER_PERSISTENT | SOCK_NOTIFY_TRIGGER_LEVEL,
-        PollMode::Edge => SOCK_NOTIFY_TRIGGER_PERSISTENT | SOCK_NOTIFY_TRIGGER_EDGE,
-        PollMode::EdgeOneshot => SOCK_NOTIFY_TRIGGER_ONESHOT | SOCK_NOTIFY_TRIGGER_EDGE,
-    };
-    flags as u8
+/// Only the key and the flags are compared. The `Internal` and
+/// `lpOverlapped` fields are ignored.
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key() && self.events() == other.events()
+    }
 }
 
-fn create_registration(
-    socket: SOCKET,
-    interest: Event,
-    mode: PollMode,
-    enable: bool,
-) -> SOCK_NOTIFY_REGISTRATION {
-    let filter = interest_to_filter(&interest);
-    SOCK_NOTIFY_REGISTRATION {
-        socket,
-        completionKey: interest.key() as _,
-        eventFilter: filter,
-        operation: if enable {
-            if filter == SOCK_NOTIFY_REGISTER_EVENT_NONE as _ {
-                SOCK_NOTIFY_OP_DISABLE as _
-            } else {
-                SOCK_NOTIFY_OP_ENABLE as _
-            }
-        } else {
-            SOCK_NOTIFY_OP_REMOVE as _
-        },
-        triggerFlags: mode_to_flags(mode),
-        registrationResult: 0,
+impl Eq for Event {}
+
+impl Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Event")
+            .field("key", &self.key())
+            .field("readable", &self.is_readable())
+            .field("writable", &self.is_writable())
+            .field("hangup", &self.is_hangup())
+            .field("error", &self.is_error())
+            .field("removed", &self.is_removed())
+            .field("events", &format_args!("{:#x}", self.events()))
+            .finish()
     }
 }
 
+fn interest_to_events(event: &Event) -> u32 {
+    Interest::from_bits_truncate(event.events()).bits()
+}
+
 fn map_try_reserve_error(e: TryReserveError) -> Error {
     match e {
-        TryReserveError::AllocError { .. } => Error(ERROR_NOT_ENOUGH_MEMORY),
-        TryReserveError::CapacityOverflow => Error(ERROR_NOT_ENOUGH_QUOTA),
+        TryReserveError::AllocError { .. } => Error::new(ERROR_NOT_ENOUGH_MEMORY),
+        TryReserveError::CapacityOverflow => Error::new(ERROR_NOT_ENOUGH_QUOTA),
     }
 }
@@ -7,7 +7,14 @@ use windows_sys::Win32::System::Threading::{
 };
 
 pub type RwLock<T> = lock_api::RwLock<SRWLock, T>;
-
+pub type RwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, SRWLock, T>;
+
+/// A read-write lock built on SRW locks.
+///
+/// It doesn't implement `RawRwLockUpgrade`. SRW locks can't be upgraded
+/// natively, and emulating it with a second lock would cost every writer one
+/// more lock. The `epoll_ctl` path takes the write lock of the poller map
+/// only to insert or remove a poller, and reads otherwise.
 pub struct SRWLock(UnsafeCell<SRWLOCK>);
 
 unsafe impl RawRwLock for SRWLock {
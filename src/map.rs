@@ -2,13 +2,20 @@ use alloc::alloc::Global;
 use core::{
     fmt::{self, Debug},
     hash::{BuildHasher, Hash},
+    marker::PhantomData,
 };
 
-use hashbrown::{Equivalent, TryReserveError, hash_map::DefaultHashBuilder, raw::RawTable};
+use hashbrown::{
+    Equivalent, TryReserveError,
+    hash_map::DefaultHashBuilder,
+    raw::{RawIter, RawTable},
+};
 
 pub struct HashMap<K, V> {
     hash_builder: DefaultHashBuilder,
     table: RawTable<(K, V), Global>,
+    /// The max number of entries of a fixed-capacity map.
+    limit: Option<usize>,
 }
 
 fn make_hasher<Q, V, S>(hash_builder: &S) -> impl Fn(&(Q, V)) -> u64 + '_
@@ -39,12 +46,34 @@ impl<K, V> HashMap<K, V> {
         Self {
             hash_builder: DefaultHashBuilder::new(),
             table: RawTable::new(),
+            limit: None,
         }
     }
-}
 
-#[cfg(test)]
-impl<K, V> HashMap<K, V> {
+    /// Creates a map holding at most `limit` entries, which never
+    /// reallocates.
+    ///
+    /// Twice the capacity is allocated, so that `RawTable` always rehashes
+    /// in place to reclaim the deleted buckets instead of growing.
+    pub fn try_with_limit(limit: usize) -> Result<Self, TryReserveError> {
+        let capacity = limit
+            .checked_mul(2)
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        Ok(Self {
+            hash_builder: DefaultHashBuilder::new(),
+            table: RawTable::try_with_capacity(capacity)?,
+            limit: Some(limit),
+        })
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.limit.is_some_and(|limit| self.len() >= limit)
+    }
+
     pub fn len(&self) -> usize {
         self.table.len()
     }
@@ -52,8 +81,75 @@ impl<K, V> HashMap<K, V> {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    pub fn clear(&mut self) {
+        self.table.clear();
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.table.drain()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            // SAFETY: the table is borrowed by the iterator.
+            inner: unsafe { self.table.iter() },
+            marker: PhantomData,
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.iter() }
+    }
 }
 
+pub struct Iter<'a, K, V> {
+    inner: RawIter<(K, V)>,
+    marker: PhantomData<&'a (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.inner.next() {
+            Some(bucket) => {
+                let (k, v) = unsafe { bucket.as_ref() };
+                Some((k, v))
+            }
+            None => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+pub struct Keys<'a, K, V> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next() {
+            Some((k, _)) => Some(k),
+            None => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> ExactSizeIterator for Keys<'_, K, V> {}
+
 impl<K, V> HashMap<K, V>
 where
     K: Eq + Hash,
@@ -113,6 +209,37 @@ where
         }
     }
 
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        if self.limit.is_some() {
+            // The capacity has been reserved.
+            return Ok(());
+        }
+        let hasher = make_hasher::<_, V, _>(&self.hash_builder);
+        self.table.try_reserve(additional, hasher)
+    }
+
+    /// Shrinks the capacity as much as possible. It rehashes all entries.
+    ///
+    /// `RawTable::shrink_to` aborts on allocation failure, so a new table is
+    /// allocated fallibly instead. If it fails, the capacity is kept.
+    pub fn shrink_to_fit(&mut self) {
+        if self.limit.is_some() {
+            return;
+        }
+        let Ok(mut table) = RawTable::try_with_capacity(self.table.len()) else {
+            return;
+        };
+        if table.capacity() >= self.table.capacity() {
+            return;
+        }
+        for (k, v) in self.table.drain() {
+            let hash = make_hash::<K, _>(&self.hash_builder, &k);
+            // SAFETY: the new table has enough capacity for all entries.
+            unsafe { table.insert_no_grow(hash, (k, v)) };
+        }
+        self.table = table;
+    }
+
     pub fn try_insert(&mut self, k: K, v: V) -> Result<(&K, &mut V), TryReserveError> {
         let hash = make_hash::<K, _>(&self.hash_builder, &k);
         let hasher = make_hasher::<_, V, _>(&self.hash_builder);
@@ -144,8 +271,8 @@ where
     }
 }
 
-impl<K, V> Debug for HashMap<K, V> {
+impl<K: Debug, V: Debug> Debug for HashMap<K, V> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("HashMap").finish()
+        f.debug_map().entries(self.iter()).finish()
     }
 }
@@ -0,0 +1,108 @@
+//! Lazily bound `ProcessSocketNotifications`.
+//!
+//! The function is only present after Windows 21H1. It is resolved from
+//! `ws2_32.dll` at the first call, so that linking the crate doesn't require
+//! the symbol, and the process could start on older systems.
+
+use core::{
+    mem::transmute,
+    ptr::null_mut,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use windows_sys::Win32::{
+    Foundation::{ERROR_NOT_SUPPORTED, HANDLE},
+    Networking::WinSock::SOCK_NOTIFY_REGISTRATION,
+    System::{
+        IO::OVERLAPPED_ENTRY,
+        LibraryLoader::{GetProcAddress, LOAD_LIBRARY_SEARCH_SYSTEM32, LoadLibraryExW},
+    },
+};
+
+type ProcessSocketNotificationsFn = unsafe extern "system" fn(
+    HANDLE,
+    u32,
+    *mut SOCK_NOTIFY_REGISTRATION,
+    u32,
+    u32,
+    *mut OVERLAPPED_ENTRY,
+    *mut u32,
+) -> u32;
+
+/// Encodes an ASCII string to UTF-16 at compile time.
+pub const fn ascii_to_wide<const N: usize>(s: &[u8; N]) -> [u16; N] {
+    let mut wide = [0; N];
+    let mut i = 0;
+    while i < N {
+        wide[i] = s[i] as u16;
+        i += 1;
+    }
+    wide
+}
+
+const WS2_32: [u16; 11] = ascii_to_wide(b"ws2_32.dll\0");
+
+const UNRESOLVED: usize = 0;
+const MISSING: usize = 1;
+
+/// The resolved function pointer, or the state.
+static PROC: AtomicUsize = AtomicUsize::new(UNRESOLVED);
+
+fn resolve() -> usize {
+    let module =
+        unsafe { LoadLibraryExW(WS2_32.as_ptr(), null_mut(), LOAD_LIBRARY_SEARCH_SYSTEM32) };
+    if module.is_null() {
+        return MISSING;
+    }
+    match unsafe { GetProcAddress(module, c"ProcessSocketNotifications".as_ptr().cast()) } {
+        Some(f) => f as usize,
+        None => MISSING,
+    }
+}
+
+fn load() -> Option<ProcessSocketNotificationsFn> {
+    let mut proc = PROC.load(Ordering::Acquire);
+    if proc == UNRESOLVED {
+        // Resolving more than once is harmless.
+        proc = resolve();
+        PROC.store(proc, Ordering::Release);
+    }
+    if proc == MISSING {
+        None
+    } else {
+        Some(unsafe { transmute::<usize, ProcessSocketNotificationsFn>(proc) })
+    }
+}
+
+/// Whether `ProcessSocketNotifications` is available.
+pub fn is_supported() -> bool {
+    load().is_some()
+}
+
+/// Calls `ProcessSocketNotifications`, or returns `ERROR_NOT_SUPPORTED` if it
+/// is not available.
+#[allow(non_snake_case)]
+pub unsafe fn ProcessSocketNotifications(
+    completionport: HANDLE,
+    registrationcount: u32,
+    registrationinfos: *mut SOCK_NOTIFY_REGISTRATION,
+    timeoutms: u32,
+    completioncount: u32,
+    completionportentries: *mut OVERLAPPED_ENTRY,
+    receivedentrycount: *mut u32,
+) -> u32 {
+    match load() {
+        Some(f) => unsafe {
+            f(
+                completionport,
+                registrationcount,
+                registrationinfos,
+                timeoutms,
+                completioncount,
+                completionportentries,
+                receivedentrycount,
+            )
+        },
+        None => ERROR_NOT_SUPPORTED,
+    }
+}
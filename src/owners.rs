@@ -0,0 +1,62 @@
+//! The process-wide owners of the registered sockets.
+//!
+//! A socket could be registered to only one port at a time. With the feature
+//! `socket-owners`, the owner of each socket is tracked, so that registering
+//! it with another poller fails before calling the system. Otherwise the
+//! functions are no-ops.
+//!
+//! The owner is released when the socket is deleted, or when a wait of the
+//! owner receives the remove event of the closed socket. If the socket is
+//! closed without either, and the handle value is reused by a new socket,
+//! adding the new one to another poller fails with `ERROR_ALREADY_EXISTS`
+//! until the old one is deleted.
+
+#[cfg(feature = "socket-owners")]
+use windows_sys::Win32::Foundation::ERROR_ALREADY_EXISTS;
+use windows_sys::Win32::Networking::WinSock::SOCKET;
+
+use crate::Result;
+#[cfg(feature = "socket-owners")]
+use crate::{Error, lock::RwLock, map::HashMap};
+
+/// The sockets and their owners, identified by the backend shared between the
+/// cloned pollers of the same port.
+#[cfg(feature = "socket-owners")]
+static OWNERS: RwLock<HashMap<SOCKET, usize>> = RwLock::new(HashMap::new());
+
+/// Marks the socket owned by `owner`. Fails with `ERROR_ALREADY_EXISTS` if it
+/// is owned by another one.
+pub fn claim(socket: SOCKET, owner: usize) -> Result<()> {
+    #[cfg(feature = "socket-owners")]
+    {
+        let mut owners = OWNERS.write();
+        match owners.get(&socket) {
+            Some(&current) if current == owner => {}
+            Some(_) => {
+                return Err(Error::new(ERROR_ALREADY_EXISTS)
+                    .with_context("socket registered with another poller"));
+            }
+            None => {
+                owners
+                    .try_insert(socket, owner)
+                    .map_err(crate::map_try_reserve_error)?;
+            }
+        }
+    }
+    #[cfg(not(feature = "socket-owners"))]
+    let _ = (socket, owner);
+    Ok(())
+}
+
+/// Forgets the owner of the socket, if it is `owner`.
+pub fn release(socket: SOCKET, owner: usize) {
+    #[cfg(feature = "socket-owners")]
+    {
+        let mut owners = OWNERS.write();
+        if owners.get(&socket) == Some(&owner) {
+            owners.remove(&socket);
+        }
+    }
+    #[cfg(not(feature = "socket-owners"))]
+    let _ = (socket, owner);
+}
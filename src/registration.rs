@@ -0,0 +1,61 @@
+//! Registrations of sockets tied to the lifetime of a value.
+
+use core::mem::ManuallyDrop;
+
+use windows_sys::Win32::Networking::WinSock::SOCKET;
+
+use crate::{Interest, PollMode, Poller, Result};
+
+/// A socket registered with a [`Poller`], and deleted when dropped.
+///
+/// Created by [`Poller::register`]. The socket should outlive the guard, so
+/// that it is deleted before being closed, and its key is not received
+/// anymore.
+///
+/// [`Poller::register`]: crate::Poller::register
+#[derive(Debug)]
+pub struct Registration<'a> {
+    poller: &'a Poller,
+    socket: SOCKET,
+}
+
+impl<'a> Registration<'a> {
+    pub(crate) fn new(poller: &'a Poller, socket: SOCKET) -> Self {
+        Self { poller, socket }
+    }
+
+    /// The registered socket.
+    pub fn socket(&self) -> SOCKET {
+        self.socket
+    }
+
+    /// The poller the socket is registered with.
+    pub fn poller(&self) -> &'a Poller {
+        self.poller
+    }
+
+    /// Modifies the registration, see [`Poller::modify`].
+    pub fn modify(&self, key: usize, interest: Interest, mode: PollMode) -> Result<()> {
+        self.poller.modify(self.socket, key, interest, mode)
+    }
+
+    /// Deletes the registration, and reports the error which is ignored when
+    /// dropped.
+    pub fn delete(self) -> Result<()> {
+        let this = ManuallyDrop::new(self);
+        this.poller.delete(this.socket)
+    }
+
+    /// Leaves the socket registered, and returns it. It should be deleted by
+    /// [`Poller::delete`] manually.
+    pub fn into_raw(self) -> SOCKET {
+        ManuallyDrop::new(self).socket
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        // The socket may have been deleted manually, or removed by the system.
+        self.poller.delete(self.socket).ok();
+    }
+}
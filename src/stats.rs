@@ -0,0 +1,97 @@
+//! Counters of the poller.
+//!
+//! They are only maintained with the feature `stats`. Otherwise [`Counters`]
+//! is empty, and counting is a no-op.
+
+#[cfg(feature = "stats")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The counted operations.
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    Registrations,
+    Waits,
+    Events,
+    RemoveReposts,
+    RemoveLoops,
+}
+
+/// The counters of a poller.
+#[cfg(feature = "stats")]
+#[derive(Debug, Default)]
+pub struct Counters {
+    registrations: AtomicU64,
+    waits: AtomicU64,
+    events: AtomicU64,
+    remove_reposts: AtomicU64,
+    remove_loops: AtomicU64,
+}
+
+/// The counters of a poller, disabled.
+#[cfg(not(feature = "stats"))]
+#[derive(Debug, Default)]
+pub struct Counters;
+
+impl Counters {
+    /// Creates the counters from zero.
+    #[cfg(feature = "stats")]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the counters, disabled.
+    #[cfg(not(feature = "stats"))]
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Adds `n` to the counter.
+    #[inline]
+    pub fn add(&self, counter: Counter, n: u64) {
+        #[cfg(feature = "stats")]
+        {
+            let counter = match counter {
+                Counter::Registrations => &self.registrations,
+                Counter::Waits => &self.waits,
+                Counter::Events => &self.events,
+                Counter::RemoveReposts => &self.remove_reposts,
+                Counter::RemoveLoops => &self.remove_loops,
+            };
+            counter.fetch_add(n, Ordering::Relaxed);
+        }
+        #[cfg(not(feature = "stats"))]
+        let _ = (counter, n);
+    }
+
+    /// Reads the counters.
+    #[cfg(feature = "stats")]
+    pub fn snapshot(&self) -> PollerStats {
+        PollerStats {
+            registrations: self.registrations.load(Ordering::Relaxed),
+            waits: self.waits.load(Ordering::Relaxed),
+            events: self.events.load(Ordering::Relaxed),
+            remove_reposts: self.remove_reposts.load(Ordering::Relaxed),
+            remove_loops: self.remove_loops.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The statistics of a [`Poller`], see [`Poller::stats`].
+///
+/// [`Poller`]: crate::Poller
+/// [`Poller::stats`]: crate::Poller::stats
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub struct PollerStats {
+    /// Sockets, waitables and file handles added.
+    pub registrations: u64,
+    /// Waits on the port, including the ones timed out.
+    pub waits: u64,
+    /// Events delivered by the waits.
+    pub events: u64,
+    /// Unrelated entries reposted while waiting for the removal of sockets.
+    pub remove_reposts: u64,
+    /// Extra waits for the removal of sockets, after the first attempt.
+    pub remove_loops: u64,
+}
@@ -0,0 +1,75 @@
+//! Safe wrapper around waitable timers.
+
+use core::{
+    ptr::{null, null_mut},
+    time::Duration,
+};
+
+use windows_sys::Win32::{
+    Foundation::{ERROR_INVALID_PARAMETER, HANDLE},
+    System::Threading::{
+        CREATE_WAITABLE_TIMER_HIGH_RESOLUTION, CancelWaitableTimer, CreateWaitableTimerExW,
+        SetWaitableTimer, TIMER_ALL_ACCESS,
+    },
+};
+
+use crate::{Error, OwnedHandle, Result};
+
+/// Wrapper of a high resolution, auto-reset waitable timer.
+#[derive(Debug)]
+pub struct WaitableTimer {
+    handle: OwnedHandle,
+}
+
+impl WaitableTimer {
+    pub fn new() -> Result<Self> {
+        let handle = unsafe {
+            CreateWaitableTimerExW(
+                null(),
+                null(),
+                CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+                TIMER_ALL_ACCESS,
+            )
+        };
+        if handle.is_null() {
+            return Err(Error::last_os_error().with_context("CreateWaitableTimerExW"));
+        }
+        let handle = unsafe { OwnedHandle::from_raw_handle(handle) };
+        Ok(Self { handle })
+    }
+
+    pub fn as_raw_handle(&self) -> HANDLE {
+        self.handle.as_raw_handle()
+    }
+
+    /// Starts the timer. It is signaled after `due`, and then every `period`
+    /// if specified. The period is rounded to milliseconds.
+    pub fn set(&self, due: Duration, period: Option<Duration>) -> Result<()> {
+        // Negative value means relative time, in 100ns.
+        let due = i64::try_from(due.as_nanos().div_ceil(100))
+            .unwrap_or(i64::MAX)
+            .wrapping_neg();
+        let period = match period {
+            Some(period) => i32::try_from(period.as_millis())
+                .map_err(|_| Error::new(ERROR_INVALID_PARAMETER))?,
+            None => 0,
+        };
+        let res =
+            unsafe { SetWaitableTimer(self.as_raw_handle(), &due, period, None, null_mut(), 0) };
+        if res == 0 {
+            Err(Error::last_os_error().with_context("SetWaitableTimer"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stops the timer.
+    pub fn cancel(&self) -> Result<()> {
+        let res = unsafe { CancelWaitableTimer(self.as_raw_handle()) };
+        if res == 0 {
+            Err(Error::last_os_error().with_context("CancelWaitableTimer"))
+        } else {
+            Ok(())
+        }
+    }
+}
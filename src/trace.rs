@@ -0,0 +1,42 @@
+//! Traces of the FFI calls, written to the debugger with
+//! `OutputDebugStringW`.
+
+use core::fmt::{self, Write};
+
+use windows_sys::Win32::System::Diagnostics::Debug::OutputDebugStringW;
+
+/// The max length of a line, including the line feed and the terminating
+/// null.
+const LINE_LEN: usize = 256;
+
+/// A line of UTF-16 on the stack. The overflow is truncated.
+struct Line {
+    buf: [u16; LINE_LEN],
+    len: usize,
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.encode_utf16() {
+            // Leave room for the line feed and the terminating null.
+            if self.len + 2 >= LINE_LEN {
+                break;
+            }
+            self.buf[self.len] = c;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a line to the debugger.
+pub fn output(args: fmt::Arguments) {
+    let mut line = Line {
+        buf: [0; LINE_LEN],
+        len: 0,
+    };
+    // The overflow is truncated instead of failing.
+    line.write_fmt(args).ok();
+    line.buf[line.len] = b'\n' as u16;
+    unsafe { OutputDebugStringW(line.buf.as_ptr()) };
+}
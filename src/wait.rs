@@ -1,5 +1,6 @@
 //! Safe wrapper around `NtAssociateWaitCompletionPacket` API series.
 
+use alloc::vec::Vec;
 use core::{ffi::c_void, ptr::null_mut};
 
 use windows_sys::{
@@ -37,64 +38,161 @@ unsafe extern "system" {
     ) -> NTSTATUS;
 }
 
+/// The result of [`WaitCompletionPacket::cancel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelResult {
+    /// The wait was cancelled before the object was signaled.
+    Cancelled,
+    /// The object was signaled, and the queued completion was removed from
+    /// the port.
+    AlreadySignaled,
+    /// The object was signaled, and the completion is queued to the port, or
+    /// will be soon. The packet is still in use until it is received.
+    Pending,
+}
+
+impl CancelResult {
+    /// Whether the packet could be associated again.
+    pub fn is_reusable(self) -> bool {
+        self != Self::Pending
+    }
+}
+
 /// Wrapper of NT WaitCompletionPacket.
 #[derive(Debug)]
 pub struct WaitCompletionPacket {
     handle: OwnedHandle,
 }
 
-fn check_status(status: NTSTATUS) -> Result<()> {
+pub fn check_status(status: NTSTATUS, context: &'static str) -> Result<()> {
     if status == STATUS_SUCCESS {
         Ok(())
     } else {
-        Err(Error(unsafe { RtlNtStatusToDosError(status) }))
+        Err(Error::new(unsafe { RtlNtStatusToDosError(status) }).with_context(context))
     }
 }
 
 impl WaitCompletionPacket {
     pub fn new() -> Result<Self> {
         let mut handle = null_mut();
-        check_status(unsafe {
-            NtCreateWaitCompletionPacket(&mut handle, GENERIC_READ | GENERIC_WRITE, null_mut())
-        })?;
+        check_status(
+            unsafe {
+                NtCreateWaitCompletionPacket(&mut handle, GENERIC_READ | GENERIC_WRITE, null_mut())
+            },
+            "NtCreateWaitCompletionPacket",
+        )?;
         let handle = unsafe { OwnedHandle::from_raw_handle(handle) };
         Ok(Self { handle })
     }
 
     /// Associate waitable object to IOCP. The parameter `info` is the
-    /// field `dwNumberOfBytesTransferred` in `OVERLAPPED_ENTRY`
-    pub fn associate(
-        &mut self,
+    /// field `dwNumberOfBytesTransferred` in `OVERLAPPED_ENTRY`, and the
+    /// waitable handle is the field `lpOverlapped`.
+    ///
+    /// If the waitable is already signaled, the packet is queued to the IOCP
+    /// immediately, so no event is lost, and posting another one would
+    /// deliver it twice. Therefore `AlreadySignaled` is not queried.
+    pub fn associate(&self, port: HANDLE, event: HANDLE, key: usize, info: usize) -> Result<()> {
+        self.associate_with_context(port, event, event, key, info)
+    }
+
+    /// Associate waitable object to IOCP. The parameter `context` is the
+    /// field `lpOverlapped` in `OVERLAPPED_ENTRY`.
+    pub fn associate_with_context(
+        &self,
+        port: HANDLE,
+        event: HANDLE,
+        context: HANDLE,
+        key: usize,
+        info: usize,
+    ) -> Result<()> {
+        self.associate_with_status(port, event, context, key, STATUS_SUCCESS, info)
+    }
+
+    /// Associate waitable object to IOCP. The parameter `status` is the field
+    /// `Internal` in `OVERLAPPED_ENTRY`.
+    pub fn associate_with_status(
+        &self,
         port: HANDLE,
         event: HANDLE,
+        context: HANDLE,
         key: usize,
+        status: NTSTATUS,
         info: usize,
     ) -> Result<()> {
-        check_status(unsafe {
-            NtAssociateWaitCompletionPacket(
-                self.handle.as_raw_handle(),
-                port,
-                event,
-                key as _,
-                null_mut(),
-                STATUS_SUCCESS,
-                info,
-                null_mut(),
-            )
-        })?;
+        check_status(
+            unsafe {
+                NtAssociateWaitCompletionPacket(
+                    self.handle.as_raw_handle(),
+                    port,
+                    event,
+                    key as _,
+                    context,
+                    status,
+                    info,
+                    null_mut(),
+                )
+            },
+            "NtAssociateWaitCompletionPacket",
+        )?;
         Ok(())
     }
 
-    /// Cancels the completion packet. The return value means:
-    /// - `Ok(true)`: cancellation is successful.
-    /// - `Ok(false)`: cancellation failed, the packet is still in use.
-    /// - `Err(e)`: other errors.
-    pub fn cancel(&mut self) -> Result<bool> {
-        let status = unsafe { NtCancelWaitCompletionPacket(self.handle.as_raw_handle(), 0) };
+    /// Cancels the completion packet. A completion already queued to the port
+    /// is kept, and [`CancelResult::Pending`] is returned.
+    pub fn cancel(&self) -> Result<CancelResult> {
+        self.cancel_with(false)
+    }
+
+    /// Cancels the completion packet, and removes the completion already
+    /// queued to the port, so that it is not received anymore.
+    /// [`CancelResult::Pending`] is returned if it is being received.
+    pub fn cancel_and_remove(&self) -> Result<CancelResult> {
+        self.cancel_with(true)
+    }
+
+    fn cancel_with(&self, remove_signaled: bool) -> Result<CancelResult> {
+        let status = unsafe {
+            NtCancelWaitCompletionPacket(self.handle.as_raw_handle(), remove_signaled as _)
+        };
         match status {
-            STATUS_SUCCESS | STATUS_CANCELLED => Ok(true),
-            STATUS_PENDING => Ok(false),
-            _ => Err(Error(unsafe { RtlNtStatusToDosError(status) })),
+            STATUS_SUCCESS => Ok(CancelResult::Cancelled),
+            STATUS_CANCELLED => Ok(CancelResult::AlreadySignaled),
+            STATUS_PENDING => Ok(CancelResult::Pending),
+            _ => Err(Error::new(unsafe { RtlNtStatusToDosError(status) })
+                .with_context("NtCancelWaitCompletionPacket")),
+        }
+    }
+}
+
+/// The max number of packets kept for reuse.
+const PACKET_POOL_SIZE: usize = 64;
+
+/// A bounded pool of cancelled packets, to avoid creating new ones.
+#[derive(Debug)]
+pub struct PacketPool {
+    packets: Vec<WaitCompletionPacket>,
+}
+
+impl PacketPool {
+    pub const fn new() -> Self {
+        Self {
+            packets: Vec::new(),
+        }
+    }
+
+    /// Takes a packet from the pool, or creates a new one.
+    pub fn take(&mut self) -> Result<WaitCompletionPacket> {
+        match self.packets.pop() {
+            Some(packet) => Ok(packet),
+            None => WaitCompletionPacket::new(),
+        }
+    }
+
+    /// Keeps a cancelled packet for reuse, or closes it if the pool is full.
+    pub fn recycle(&mut self, packet: WaitCompletionPacket) {
+        if self.packets.len() < PACKET_POOL_SIZE && self.packets.try_reserve(1).is_ok() {
+            self.packets.push(packet);
         }
     }
 }
@@ -0,0 +1,46 @@
+//! Wake up alertable waits of a thread with user APCs.
+
+use windows_sys::Win32::{
+    Foundation::HANDLE,
+    System::Threading::{GetCurrentThread, QueueUserAPC},
+};
+
+use crate::{Error, OwnedHandle, Result};
+
+unsafe extern "system" fn noop_apc(_: usize) {}
+
+/// Queue an APC doing nothing to the thread.
+pub fn wake_thread(thread: HANDLE) -> Result<()> {
+    let res = unsafe { QueueUserAPC(Some(noop_apc), thread, 0) };
+    if res == 0 {
+        Err(Error::last_os_error().with_context("QueueUserAPC"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Wakes up the alertable waits of a thread.
+///
+/// Created by [`Poller::waker_for_current_thread`]. It could be sent to and
+/// used by other threads.
+///
+/// [`Poller::waker_for_current_thread`]: crate::Poller::waker_for_current_thread
+#[derive(Debug)]
+pub struct ThreadWaker {
+    thread: OwnedHandle,
+}
+
+impl ThreadWaker {
+    pub(crate) fn current() -> Result<Self> {
+        let thread = OwnedHandle::duplicate(unsafe { GetCurrentThread() })?;
+        Ok(Self { thread })
+    }
+
+    /// Wakes up the thread if it is waiting with `alertable` set.
+    ///
+    /// If the thread is not waiting, the next alertable wait returns
+    /// immediately.
+    pub fn wake(&self) -> Result<()> {
+        wake_thread(self.thread.as_raw_handle())
+    }
+}
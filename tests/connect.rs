@@ -1,13 +1,14 @@
 use std::{
-    io::Read,
+    io::{Read, Write},
     mem::MaybeUninit,
     net::{Ipv4Addr, TcpListener},
     os::windows::io::AsRawSocket,
+    thread,
     time::Duration,
 };
 
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
-use wepoll2::{Event, PollMode, Poller};
+use wepoll2::{Event, Interest, PollMode, Poller};
 
 #[test]
 fn poll_connect() {
@@ -17,10 +18,14 @@ fn poll_connect() {
     let client = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
     client.set_nonblocking(true).unwrap();
 
-    let mut poller = Poller::new().unwrap();
-    let interest = Event::none(114514).with_writable(true);
+    let poller = Poller::new().unwrap();
     poller
-        .add(client.as_raw_socket() as _, interest, PollMode::Level)
+        .add(
+            client.as_raw_socket() as _,
+            114514,
+            Interest::WRITABLE,
+            PollMode::Level,
+        )
         .unwrap();
 
     let e = client.connect(&SockAddr::from(addr)).unwrap_err();
@@ -68,3 +73,216 @@ fn poll_connect() {
 
     poller.delete(client.as_raw_socket() as _).unwrap();
 }
+
+#[test]
+fn split() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    client.connect(&SockAddr::from(addr)).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+    server.write_all(b"hello").unwrap();
+
+    let poller = Poller::new().unwrap();
+    let socket = client.as_raw_socket() as _;
+    poller.add_split(socket, 1, 2, PollMode::Level).unwrap();
+    assert!(poller.add_split(socket, 3, 3, PollMode::Level).is_err());
+
+    let (mut readable, mut writable) = (false, false);
+    let mut entries = [MaybeUninit::uninit(); 8];
+    while !(readable && writable) {
+        let len = poller.wait(&mut entries, None, false).unwrap();
+        for event in &entries[..len] {
+            let event = unsafe { event.assume_init_ref() };
+            match event.key() {
+                1 => {
+                    assert!(!event.is_writable());
+                    readable |= event.is_readable();
+                }
+                2 => {
+                    assert!(event.is_writable());
+                    assert!(!event.is_readable());
+                    writable = true;
+                }
+                key => panic!("unexpected key {key}"),
+            }
+        }
+    }
+
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn rearm() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    client.connect(&SockAddr::from(addr)).unwrap();
+    let _server = listener.accept().unwrap();
+
+    let poller = Poller::new().unwrap();
+    let socket = client.as_raw_socket() as _;
+    poller
+        .add(socket, 1, Interest::WRITABLE, PollMode::Oneshot)
+        .unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    for _ in 0..3 {
+        let len = poller
+            .wait(&mut entries, Some(Duration::from_secs(1)), false)
+            .unwrap();
+        assert_eq!(len, 1);
+        let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+        assert!(event.is_writable());
+
+        // Oneshot, no more events until rearmed.
+        let len = poller
+            .wait(&mut entries, Some(Duration::ZERO), false)
+            .unwrap();
+        assert_eq!(len, 0);
+        poller.rearm(socket).unwrap();
+    }
+
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn delete_while_waiting() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    client.connect(&SockAddr::from(addr)).unwrap();
+    let (_server, _) = listener.accept().unwrap();
+
+    let poller = Poller::new().unwrap();
+    let socket = client.as_raw_socket() as _;
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+
+    let events = thread::scope(|s| {
+        let waiter = s.spawn(|| {
+            let mut entries = [MaybeUninit::uninit(); 8];
+            let len = poller
+                .wait(&mut entries, Some(Duration::from_secs(5)), false)
+                .unwrap();
+            entries[..len]
+                .iter()
+                .map(|event| {
+                    let event = unsafe { event.assume_init_ref() };
+                    (event.key(), event.is_removed())
+                })
+                .collect::<Vec<_>>()
+        });
+        thread::sleep(Duration::from_millis(100));
+        // The REMOVE event may be received by the waiter, and is handed over.
+        poller.delete(socket).unwrap();
+        poller.post(Event::none(2)).unwrap();
+        waiter.join().unwrap()
+    });
+
+    assert_eq!(events, [(2, false)]);
+}
+
+#[test]
+fn poll_once() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    client.connect(&SockAddr::from(addr)).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+
+    let poller = Poller::new().unwrap();
+    let socket = client.as_raw_socket() as _;
+    let event = poller
+        .poll_once(socket, Interest::WRITABLE, Some(Duration::from_secs(1)))
+        .unwrap();
+    assert!(event.is_writable());
+    assert_eq!(event.key(), socket);
+
+    // Nothing to read yet.
+    let event = poller
+        .poll_once(socket, Interest::READABLE, Some(Duration::from_millis(100)))
+        .unwrap();
+    assert!(!event.is_readable());
+
+    server.write_all(b"hello").unwrap();
+    let event = poller
+        .poll_once(socket, Interest::READABLE, Some(Duration::from_secs(1)))
+        .unwrap();
+    assert!(event.is_readable());
+    assert!(!poller.contains_socket(socket));
+
+    // The shutdown event ends probing, and is left for the waits.
+    poller.shutdown().unwrap();
+    let event = poller.poll_once(socket, Interest::READABLE, None).unwrap();
+    assert!(event.is_shutdown());
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller
+        .wait(&mut entries, Some(Duration::ZERO), false)
+        .unwrap();
+    assert_eq!(len, 1);
+    assert!(unsafe { entries[0].assume_init_ref() }.is_shutdown());
+}
+
+#[cfg(feature = "afd")]
+#[test]
+fn afd() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    client.connect(&SockAddr::from(addr)).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+
+    let poller = Poller::with_afd().unwrap();
+    let socket = client.as_raw_socket() as _;
+    assert!(
+        poller
+            .add(socket, 1, Interest::READABLE, PollMode::Edge)
+            .is_err()
+    );
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Oneshot)
+        .unwrap();
+
+    // Nothing to read yet.
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller
+        .wait(&mut entries, Some(Duration::from_millis(100)), false)
+        .unwrap();
+    assert_eq!(len, 0);
+
+    server.write_all(b"hello").unwrap();
+    let len = poller
+        .wait(&mut entries, Some(Duration::from_secs(1)), false)
+        .unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert_eq!(event.key(), 1);
+    assert!(event.is_readable());
+
+    // The completion of the writable request is dropped after the interest
+    // changes, and the wait goes on for the readable one.
+    poller
+        .modify(socket, 1, Interest::WRITABLE, PollMode::Level)
+        .unwrap();
+    poller
+        .modify(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    let len = poller
+        .wait(&mut entries, Some(Duration::from_secs(1)), false)
+        .unwrap();
+    assert!(len > 0);
+    for event in &entries[..len] {
+        let event = unsafe { event.assume_init_ref() };
+        assert_eq!(event.key(), 1);
+        assert!(event.is_readable());
+    }
+
+    poller.delete(socket).unwrap();
+}
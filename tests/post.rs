@@ -0,0 +1,525 @@
+use std::{mem::MaybeUninit, thread, time::Duration};
+
+use wepoll2::{Event, EventRing, Events, Fairness, Poller, Readiness, WaitOutcome};
+use windows_sys::Win32::{
+    Networking::WinSock::SOCK_NOTIFY_EVENT_REMOVE, System::IO::PostQueuedCompletionStatus,
+};
+
+#[test]
+fn overflow() {
+    let poller = Poller::new().unwrap();
+    for key in 0..3 {
+        poller.post(Event::none(key).with_readable(true)).unwrap();
+    }
+
+    let mut entries = [MaybeUninit::uninit(); 2];
+    let (len, more) = poller
+        .wait_with_overflow(&mut entries, Some(Duration::ZERO), false)
+        .unwrap();
+    assert_eq!(len, 2);
+    assert!(more);
+
+    let (len, more) = poller
+        .wait_with_overflow(&mut entries, Some(Duration::ZERO), false)
+        .unwrap();
+    assert_eq!(len, 1);
+    assert!(!more);
+}
+
+#[test]
+fn notify() {
+    let poller = Poller::new().unwrap();
+    poller.notify().unwrap();
+    poller.notify().unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert!(event.is_notify());
+
+    // The notification is received, and a new one could be queued.
+    poller.notify().unwrap();
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+
+    assert!(poller.post(Event::none(usize::MAX)).is_err());
+}
+
+#[test]
+fn try_clone() {
+    let poller = Poller::new().unwrap();
+    let cloned = poller.try_clone().unwrap();
+    poller.post(Event::none(114514)).unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = cloned.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert_eq!(event.key(), 114514);
+}
+
+#[test]
+fn wait_iter() {
+    let poller = Poller::new().unwrap();
+    for key in 0..3 {
+        poller.post(Event::none(key).with_readable(true)).unwrap();
+    }
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let keys = poller
+        .wait_iter(&mut entries, None, false)
+        .unwrap()
+        .map(|e| e.key())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, [0, 1, 2]);
+}
+
+#[test]
+fn wait_events() {
+    let poller = Poller::new().unwrap();
+    for key in 0..3 {
+        poller.post(Event::none(key).with_readable(true)).unwrap();
+    }
+
+    let mut events = Events::with_capacity(2).unwrap();
+    let len = poller.wait_events(&mut events, None).unwrap();
+    assert_eq!(len, 2);
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|e| e.is_readable()));
+
+    let len = poller.wait_events(&mut events, None).unwrap();
+    assert_eq!(len, 1);
+    assert_eq!(events.iter().next().unwrap().key(), 2);
+
+    events.clear();
+    assert!(events.is_empty());
+}
+
+#[test]
+fn drain() {
+    let poller = Poller::new().unwrap();
+    for key in 0..100 {
+        poller.post(Event::none(key).with_readable(true)).unwrap();
+    }
+
+    let mut sink = vec![];
+    let len = poller.drain(&mut sink).unwrap();
+    assert_eq!(len, 100);
+    assert!(sink.iter().map(|e| e.key()).eq(0..100));
+
+    let len = poller.drain(&mut sink).unwrap();
+    assert_eq!(len, 0);
+    assert_eq!(sink.len(), 100);
+
+    // The shutdown event fills the first chunk, and ends draining though it
+    // is posted back.
+    for key in 0..63 {
+        poller.post(Event::none(key)).unwrap();
+    }
+    poller.shutdown().unwrap();
+    let len = poller.drain(&mut sink).unwrap();
+    assert_eq!(len, 64);
+    assert!(sink[163].is_shutdown());
+}
+
+#[test]
+fn event_eq() {
+    let poller = Poller::new().unwrap();
+    let event = Event::none(114514).with_writable(true);
+    poller.post(event).unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    assert_eq!(unsafe { entries[0].assume_init() }, event);
+}
+
+#[test]
+fn wait_init() {
+    let poller = Poller::new().unwrap();
+    poller.post(Event::readable(114514)).unwrap();
+
+    let mut events = vec![Event::none(0); 8];
+    let len = poller.wait_init(&mut events, None, false).unwrap();
+    assert_eq!(len, 1);
+    assert_eq!(events[0], Event::readable(114514));
+}
+
+#[test]
+fn cancel_wait() {
+    let poller = Poller::new().unwrap();
+    let waker = poller.waker_for_current_thread().unwrap();
+    let handle = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        waker.wake().unwrap();
+    });
+
+    let mut entries = [MaybeUninit::uninit(); 1];
+    let len = poller.wait(&mut entries, None, true).unwrap();
+    assert_eq!(len, 0);
+    handle.join().unwrap();
+}
+
+#[test]
+fn wait_ex() {
+    let poller = Poller::new().unwrap();
+    let mut entries = [MaybeUninit::uninit(); 1];
+    let outcome = poller
+        .wait_ex(&mut entries, Some(Duration::ZERO), false)
+        .unwrap();
+    assert_eq!(outcome, WaitOutcome::TimedOut);
+
+    poller.waker_for_current_thread().unwrap().wake().unwrap();
+    let outcome = poller.wait_ex(&mut entries, None, true).unwrap();
+    assert_eq!(outcome, WaitOutcome::Interrupted);
+
+    poller.post(Event::readable(1)).unwrap();
+    let outcome = poller.wait_ex(&mut entries, None, false).unwrap();
+    assert_eq!(outcome, WaitOutcome::Events(1));
+}
+
+#[test]
+fn wait_into_ring() {
+    let poller = Poller::new().unwrap();
+    let mut ring = EventRing::with_capacity(4).unwrap();
+    for key in 0..3 {
+        poller.post(Event::readable(key)).unwrap();
+    }
+    let len = poller
+        .wait_into_ring(&mut ring, Some(Duration::ZERO))
+        .unwrap();
+    assert_eq!(len, 3);
+    assert_eq!(ring.pop().unwrap().key(), 0);
+    assert_eq!(ring.pop().unwrap().key(), 1);
+
+    // The free slots wrap around.
+    for key in 3..6 {
+        poller.post(Event::readable(key)).unwrap();
+    }
+    let len = poller
+        .wait_into_ring(&mut ring, Some(Duration::ZERO))
+        .unwrap();
+    assert_eq!(len, 3);
+    assert!(ring.is_full());
+    let (first, second) = ring.as_slices();
+    let keys = first
+        .iter()
+        .chain(second)
+        .map(Event::key)
+        .collect::<Vec<_>>();
+    assert_eq!(keys, [2, 3, 4, 5]);
+
+    // It doesn't block when full.
+    let len = poller.wait_into_ring(&mut ring, None).unwrap();
+    assert_eq!(len, 0);
+}
+
+#[test]
+fn as_raw_handle() {
+    let poller = Poller::new().unwrap();
+    let res =
+        unsafe { PostQueuedCompletionStatus(poller.as_raw_handle(), 7, 42, std::ptr::null_mut()) };
+    assert_ne!(res, 0);
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert_eq!(event.key(), 42);
+    assert_eq!(event.0.dwNumberOfBytesTransferred, 7);
+}
+
+#[test]
+fn post_bytes() {
+    let poller = Poller::new().unwrap();
+    poller.post_bytes(7, 0xdead_beef).unwrap();
+    assert!(poller.post_bytes(usize::MAX, 0).is_err());
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert_eq!(event.key(), 7);
+    assert_eq!(event.events(), 0xdead_beef);
+}
+
+#[test]
+fn removed() {
+    let poller = Poller::new().unwrap();
+    poller.post_bytes(1, SOCK_NOTIFY_EVENT_REMOVE).unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert!(event.is_removed());
+    assert!(!Event::readable(1).is_removed());
+}
+
+#[test]
+fn fairness() {
+    let poller = Poller::new().unwrap();
+    poller.set_fairness(Fairness::RoundRobin);
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    for expected in [[0, 1, 2], [1, 2, 0], [2, 0, 1]] {
+        for key in 0..3 {
+            poller.post(Event::none(key)).unwrap();
+        }
+        let len = poller.wait(&mut entries, None, false).unwrap();
+        assert_eq!(len, 3);
+        let keys = entries[..len]
+            .iter()
+            .map(|event| unsafe { event.assume_init_ref() }.key())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, expected);
+    }
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn stats() {
+    let poller = Poller::new().unwrap();
+    for key in 0..3 {
+        poller.post(Event::none(key)).unwrap();
+    }
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 3);
+    let len = poller
+        .wait(&mut entries, Some(Duration::ZERO), false)
+        .unwrap();
+    assert_eq!(len, 0);
+
+    let stats = poller.stats();
+    assert_eq!(stats.waits, 2);
+    assert_eq!(stats.events, 3);
+    assert_eq!(stats.registrations, 0);
+}
+
+#[test]
+fn for_each() {
+    let poller = Poller::new().unwrap();
+    for key in 0..100 {
+        poller.post(Event::none(key)).unwrap();
+    }
+
+    let mut keys = vec![];
+    let len = poller
+        .wait_for_each(80, None, false, |event| keys.push(event.key()))
+        .unwrap();
+    assert_eq!(len, 80);
+    assert_eq!(keys, (0..80).collect::<Vec<_>>());
+
+    keys.clear();
+    let len = poller
+        .wait_for_each(80, None, false, |event| keys.push(event.key()))
+        .unwrap();
+    assert_eq!(len, 20);
+    assert_eq!(keys, (80..100).collect::<Vec<_>>());
+
+    let len = poller
+        .wait_for_each(0, None, false, |_| unreachable!())
+        .unwrap();
+    assert_eq!(len, 0);
+
+    // The shutdown event fills the first chunk, and ends the chunks though
+    // it is posted back.
+    for key in 0..63 {
+        poller.post(Event::none(key)).unwrap();
+    }
+    poller.shutdown().unwrap();
+    let mut shutdowns = 0;
+    let len = poller
+        .wait_for_each(200, None, false, |event| {
+            shutdowns += event.is_shutdown() as usize
+        })
+        .unwrap();
+    assert_eq!(len, 64);
+    assert_eq!(shutdowns, 1);
+}
+
+#[test]
+fn flush_pending() {
+    let poller = Poller::new().unwrap();
+    for key in [1, 2, 1, 3, 1] {
+        poller.post(Event::none(key)).unwrap();
+    }
+
+    assert_eq!(poller.flush_pending(1).unwrap(), 3);
+    assert_eq!(poller.flush_pending(4).unwrap(), 0);
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller
+        .wait(&mut entries, Some(Duration::ZERO), false)
+        .unwrap();
+    let keys = entries[..len]
+        .iter()
+        .map(|event| unsafe { event.assume_init_ref() }.key())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, [2, 3]);
+}
+
+#[test]
+fn concurrency() {
+    let poller = Poller::with_concurrency(1).unwrap();
+    poller.post(Event::readable(1)).unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+}
+
+#[test]
+fn concurrent_waits() {
+    const WAITERS: usize = 4;
+    const EVENTS: usize = 10000;
+    // The keys from `EVENTS` stop the waiters.
+    const STOP: usize = EVENTS;
+
+    let poller = Poller::new().unwrap();
+    let mut keys = thread::scope(|s| {
+        let waiters = (0..WAITERS)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut keys = vec![];
+                    let mut entries = [MaybeUninit::uninit(); 8];
+                    loop {
+                        let len = poller.wait(&mut entries, None, false).unwrap();
+                        let mut stopped = false;
+                        for event in &entries[..len] {
+                            let key = unsafe { event.assume_init_ref() }.key();
+                            if key < STOP {
+                                keys.push(key);
+                            } else if stopped {
+                                // Leave it for another waiter.
+                                poller.post(Event::none(key)).unwrap();
+                            } else {
+                                stopped = true;
+                            }
+                        }
+                        if stopped {
+                            break keys;
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for key in 0..EVENTS {
+            poller.post(Event::none(key)).unwrap();
+        }
+        for key in 0..WAITERS {
+            poller.post(Event::none(STOP + key)).unwrap();
+        }
+        waiters
+            .into_iter()
+            .flat_map(|waiter| waiter.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    // No event is lost or received twice.
+    keys.sort_unstable();
+    assert_eq!(keys, (0..EVENTS).collect::<Vec<_>>());
+}
+
+#[test]
+fn shutdown() {
+    let poller = Poller::new().unwrap();
+    thread::scope(|s| {
+        let waiters = (0..4)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut entries = [MaybeUninit::uninit(); 8];
+                    loop {
+                        let len = poller.wait(&mut entries, None, false).unwrap();
+                        if entries[..len]
+                            .iter()
+                            .any(|event| unsafe { event.assume_init_ref() }.is_shutdown())
+                        {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        poller.shutdown().unwrap();
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+    });
+
+    // The later waits receive it, too.
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert!(event.is_shutdown());
+    assert!(event.is_notify());
+    assert!(!Event::none(1).is_shutdown());
+}
+
+#[test]
+fn readiness() {
+    let event = Event::readable(1).with_hangup(true);
+    let readiness = event.readiness();
+    assert!(readiness.contains(Readiness::READABLE | Readiness::HANGUP));
+    assert!(!readiness.is_writable());
+    assert_eq!(
+        readiness.iter().collect::<Vec<_>>(),
+        [Readiness::READABLE, Readiness::HANGUP]
+    );
+    assert_eq!(readiness.to_string(), "READABLE | HANGUP");
+    assert_eq!(Event::none(1).readiness().to_string(), "NONE");
+}
+
+#[test]
+fn coalesced() {
+    let poller = Poller::new().unwrap();
+    poller.post(Event::readable(1)).unwrap();
+    poller.post(Event::readable(2)).unwrap();
+    poller.post(Event::writable(1)).unwrap();
+    poller.post(Event::none(3).with_hangup(true)).unwrap();
+    poller.post(Event::none(2).with_error(true)).unwrap();
+    // Not a readiness event, and passed through.
+    poller.post_bytes(1, 0xdead_beef).unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait_coalesced(&mut entries, None, false).unwrap();
+    assert_eq!(len, 4);
+    let last = unsafe { entries[3].assume_init_ref() };
+    assert_eq!(last.key(), 1);
+    assert_eq!(last.events(), 0xdead_beef);
+    let events = entries[..3]
+        .iter()
+        .map(|event| {
+            let event = unsafe { event.assume_init_ref() };
+            (event.key(), event.readiness())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [
+            (1, Readiness::READABLE | Readiness::WRITABLE),
+            (2, Readiness::READABLE | Readiness::ERROR),
+            (3, Readiness::HANGUP),
+        ]
+    );
+}
+
+#[test]
+fn alertable() {
+    let poller = Poller::new().unwrap();
+    poller.set_alertable(true);
+
+    // The queued APC interrupts the infinite wait.
+    poller.waker_for_current_thread().unwrap().wake().unwrap();
+    let mut entries = [MaybeUninit::uninit(); 1];
+    let len = poller.poll(&mut entries, None).unwrap();
+    assert_eq!(len, 0);
+
+    poller.post(Event::readable(1)).unwrap();
+    let len = poller.poll(&mut entries, None).unwrap();
+    assert_eq!(len, 1);
+}
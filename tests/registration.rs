@@ -0,0 +1,688 @@
+use std::{
+    mem::MaybeUninit,
+    net::{Ipv4Addr, TcpListener},
+    os::windows::io::AsRawSocket,
+    thread,
+    time::Duration,
+};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use wepoll2::{Error, Event, Interest, PollMode, Poller, RegistrationError};
+use windows_sys::Win32::{
+    Foundation::{
+        ERROR_ALREADY_EXISTS, ERROR_NO_SYSTEM_RESOURCES, ERROR_NOT_ENOUGH_MEMORY,
+        ERROR_NOT_ENOUGH_QUOTA, ERROR_NOT_FOUND, HANDLE,
+    },
+    Networking::WinSock::{SOCK_NOTIFY_EVENT_REMOVE, WSAENOBUFS},
+};
+
+#[test]
+#[cfg_attr(feature = "force-afd", ignore = "AFD doesn't support the edge modes")]
+fn interest() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    assert!(poller.interest(socket).is_none());
+
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    let (key, interest, mode) = poller.interest(socket).unwrap();
+    assert_eq!(key, 1);
+    assert_eq!(interest, Interest::READABLE);
+    assert_eq!(mode, PollMode::Level);
+
+    poller
+        .modify(socket, 1, Interest::WRITABLE, PollMode::Edge)
+        .unwrap();
+    let (key, interest, mode) = poller.interest(socket).unwrap();
+    assert_eq!(key, 1);
+    assert_eq!(interest, Interest::WRITABLE);
+    assert_eq!(mode, PollMode::Edge);
+
+    poller.delete(socket).unwrap();
+    assert!(poller.interest(socket).is_none());
+}
+
+#[test]
+#[cfg_attr(feature = "force-afd", ignore = "AFD doesn't support the edge modes")]
+fn modify_replace() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    let old = poller
+        .modify_replace(socket, 1, Interest::ALL, PollMode::Edge)
+        .unwrap();
+    assert_eq!(old, (1, Interest::READABLE, PollMode::Level));
+    let old = poller
+        .modify_replace(socket, 1, Interest::WRITABLE, PollMode::Oneshot)
+        .unwrap();
+    assert_eq!(old, (1, Interest::ALL, PollMode::Edge));
+
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn add_or_modify() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::with_socket_lookup().unwrap();
+    poller
+        .add_or_modify(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    assert_eq!(
+        poller.interest(socket),
+        Some((1, Interest::READABLE, PollMode::Level))
+    );
+    poller
+        .add_or_modify(socket, 2, Interest::WRITABLE, PollMode::Oneshot)
+        .unwrap();
+    assert_eq!(
+        poller.interest(socket),
+        Some((2, Interest::WRITABLE, PollMode::Oneshot))
+    );
+    assert_eq!(poller.socket_for_key(1), None);
+    assert_eq!(poller.socket_for_key(2), Some(socket));
+
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn disable() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add(socket, 1, Interest::WRITABLE, PollMode::Level)
+        .unwrap();
+    poller.disable(socket).unwrap();
+    assert_eq!(
+        poller.interest(socket),
+        Some((1, Interest::WRITABLE, PollMode::Level))
+    );
+    poller.enable(socket).unwrap();
+    assert_eq!(
+        poller.interest(socket),
+        Some((1, Interest::WRITABLE, PollMode::Level))
+    );
+
+    poller.delete(socket).unwrap();
+    assert!(poller.disable(socket).is_err());
+    assert!(poller.enable(socket).is_err());
+}
+
+#[test]
+#[cfg_attr(feature = "force-afd", ignore = "AFD doesn't support the edge modes")]
+fn interest_delta() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    poller
+        .add_interest(socket, Interest::WRITABLE, PollMode::Edge)
+        .unwrap();
+    assert_eq!(
+        poller.interest(socket).unwrap(),
+        (1, Interest::READABLE | Interest::WRITABLE, PollMode::Edge)
+    );
+
+    poller.remove_interest(socket, Interest::READABLE).unwrap();
+    assert_eq!(
+        poller.interest(socket).unwrap(),
+        (1, Interest::WRITABLE, PollMode::Edge)
+    );
+
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn len() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    assert!(poller.is_empty());
+
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    assert_eq!(poller.len(), 1);
+
+    // Changing the key removes and re-adds the registration internally.
+    poller
+        .modify(socket, 2, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    assert_eq!(poller.len(), 1);
+
+    poller.delete(socket).unwrap();
+    assert!(poller.is_empty());
+}
+
+#[test]
+fn contains() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    assert!(!poller.contains_socket(socket));
+
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    assert!(poller.contains_socket(socket));
+
+    poller.delete(socket).unwrap();
+    assert!(!poller.contains_socket(socket));
+}
+
+#[test]
+fn modify_key() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    s.connect(&SockAddr::from(addr)).unwrap();
+    let _server = listener.accept().unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::with_socket_lookup().unwrap();
+    poller
+        .add(socket, 1, Interest::WRITABLE, PollMode::Level)
+        .unwrap();
+    poller.modify_key(socket, 2).unwrap();
+    let (key, interest, mode) = poller.interest(socket).unwrap();
+    assert_eq!(key, 2);
+    assert_eq!(interest, Interest::WRITABLE);
+    assert_eq!(mode, PollMode::Level);
+    assert_eq!(poller.socket_for_key(1), None);
+    assert_eq!(poller.socket_for_key(2), Some(socket));
+
+    // The connected socket is writable, and reported with the new key only.
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller
+        .wait(&mut entries, Some(Duration::from_secs(1)), false)
+        .unwrap();
+    assert!(len > 0);
+    for event in &entries[..len] {
+        let event = unsafe { event.assume_init_ref() };
+        assert_eq!(event.key(), 2);
+        assert!(event.is_writable());
+    }
+
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn socket_for_key() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::with_socket_lookup().unwrap();
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    assert_eq!(poller.socket_for_key(1), Some(socket));
+
+    poller
+        .modify(socket, 2, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    assert_eq!(poller.socket_for_key(1), None);
+    assert_eq!(Event::none(2).socket(&poller), Some(socket));
+
+    poller.delete(socket).unwrap();
+    assert_eq!(poller.socket_for_key(2), None);
+}
+
+#[test]
+fn add_while_waiting() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    thread::scope(|scope| {
+        let waiter = scope.spawn(|| {
+            let mut events = [MaybeUninit::uninit(); 1];
+            poller
+                .wait(&mut events, Some(Duration::from_secs(1)), false)
+                .unwrap()
+        });
+        // The registration doesn't block on the waiting thread.
+        poller
+            .add(socket, 1, Interest::WRITABLE, PollMode::Oneshot)
+            .unwrap();
+        assert!(poller.contains_socket(socket));
+        waiter.join().unwrap();
+    });
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn sockets() {
+    let s1 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let s2 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket1 = s1.as_raw_socket() as _;
+    let socket2 = s2.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    assert_eq!(poller.sockets().count(), 0);
+
+    poller
+        .add(socket1, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    poller
+        .add(socket2, 2, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    let mut sockets = poller.sockets().collect::<Vec<_>>();
+    sockets.sort();
+    let mut expected = vec![socket1, socket2];
+    expected.sort();
+    assert_eq!(sockets, expected);
+
+    poller.delete(socket1).unwrap();
+    poller.delete(socket2).unwrap();
+}
+
+#[test]
+fn clear() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    poller.add_timer(2, Duration::from_secs(10), None).unwrap();
+    assert_eq!(poller.len(), 2);
+
+    poller.clear().unwrap();
+    assert!(poller.is_empty());
+
+    // The socket could be registered again.
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn reserve() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::with_socket_lookup().unwrap();
+    poller.reserve(16).unwrap();
+    assert!(poller.reserve(usize::MAX).is_err());
+
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn shrink_to_fit() {
+    let sockets = (0..32)
+        .map(|_| Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap())
+        .collect::<Vec<_>>();
+
+    let poller = Poller::with_socket_lookup().unwrap();
+    for (key, s) in sockets.iter().enumerate() {
+        poller
+            .add(
+                s.as_raw_socket() as _,
+                key,
+                Interest::READABLE,
+                PollMode::Level,
+            )
+            .unwrap();
+    }
+    for s in &sockets[1..] {
+        poller.delete(s.as_raw_socket() as _).unwrap();
+    }
+
+    poller.shrink_to_fit();
+    let socket = sockets[0].as_raw_socket() as _;
+    assert_eq!(poller.len(), 1);
+    assert_eq!(poller.interest(socket).unwrap().0, 0);
+    assert_eq!(poller.socket_for_key(0), Some(socket));
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn with_capacity() {
+    let s1 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let s2 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket1 = s1.as_raw_socket() as _;
+    let socket2 = s2.as_raw_socket() as _;
+
+    let poller = Poller::with_capacity(1).unwrap();
+    poller
+        .add(socket1, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    let err = poller
+        .add(socket2, 2, Interest::READABLE, PollMode::Level)
+        .unwrap_err();
+    assert_eq!(err.0, ERROR_NOT_ENOUGH_QUOTA);
+    assert!(err.is_quota_exhausted());
+    assert!(poller.reserve(1).is_err());
+
+    // The room is reused after deletion.
+    poller.delete(socket1).unwrap();
+    poller
+        .add(socket2, 2, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    poller.delete(socket2).unwrap();
+
+    // The file handles are limited as well.
+    let s3 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let s4 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let handle3 = s3.as_raw_socket() as HANDLE;
+    let handle4 = s4.as_raw_socket() as HANDLE;
+    poller.add_handle(handle3, 3).unwrap();
+    let err = poller.add_handle(handle4, 4).unwrap_err();
+    assert_eq!(err.0, ERROR_NOT_ENOUGH_QUOTA);
+    poller.delete_handle(handle3).unwrap();
+}
+
+#[test]
+fn unique_keys() {
+    let s1 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let s2 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket1 = s1.as_raw_socket() as _;
+    let socket2 = s2.as_raw_socket() as _;
+
+    let poller = Poller::with_unique_keys().unwrap();
+    poller
+        .add(socket1, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    let err = poller
+        .add(socket2, 1, Interest::READABLE, PollMode::Level)
+        .unwrap_err();
+    assert_eq!(err.0, ERROR_ALREADY_EXISTS);
+    let err = poller
+        .add_timer(1, Duration::from_secs(10), None)
+        .unwrap_err();
+    assert_eq!(err.0, ERROR_ALREADY_EXISTS);
+
+    poller
+        .add(socket2, 2, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    let err = poller.modify_key(socket2, 1).unwrap_err();
+    assert_eq!(err.0, ERROR_ALREADY_EXISTS);
+
+    // The key is released after deletion.
+    poller.delete(socket1).unwrap();
+    poller.modify_key(socket2, 1).unwrap();
+    let timer = poller.add_timer(2, Duration::from_secs(10), None).unwrap();
+
+    poller.delete_waitable(timer).unwrap();
+    poller.delete(socket2).unwrap();
+}
+
+#[test]
+fn error_context() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    let err = poller
+        .modify(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap_err();
+    assert_eq!(err.context(), Some("modify socket"));
+    assert_eq!(err.registration_error(), RegistrationError::NotFound);
+    let err = poller.delete(socket).unwrap_err();
+    assert_eq!(err.context(), Some("delete socket"));
+}
+
+#[test]
+fn handle() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let handle = s.as_raw_socket() as HANDLE;
+
+    let poller = Poller::new().unwrap();
+    poller.add_handle(handle, 1).unwrap();
+    assert!(poller.contains_handle(handle));
+    assert_eq!(
+        poller.add_handle(handle, 2).unwrap_err().0,
+        ERROR_ALREADY_EXISTS
+    );
+
+    poller.delete_handle(handle).unwrap();
+    assert!(!poller.contains_handle(handle));
+    assert!(poller.delete_handle(handle).is_err());
+}
+
+#[test]
+fn debug() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add(socket, 114514, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    assert!(format!("{poller:?}").contains("sockets: 1"));
+    let alternate = format!("{poller:#?}");
+    assert!(alternate.contains(&format!("{socket}: 114514")));
+
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+#[cfg_attr(feature = "force-afd", ignore = "AFD doesn't report the removal")]
+fn auto_cleanup() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    poller.set_auto_cleanup(true);
+    poller
+        .add(socket, 5, Interest::READABLE, PollMode::Oneshot)
+        .unwrap();
+
+    // The socket is still open, e.g., the event is left by a timed out removal.
+    poller.post_bytes(5, SOCK_NOTIFY_EVENT_REMOVE).unwrap();
+    let mut entries = [MaybeUninit::uninit(); 8];
+    poller
+        .wait(&mut entries, Some(Duration::from_secs(1)), false)
+        .unwrap();
+    assert!(poller.contains_socket(socket));
+
+    // The registration is torn down by the system after the socket is closed.
+    drop(s);
+    let mut removed = false;
+    for _ in 0..10 {
+        let len = poller
+            .wait(&mut entries, Some(Duration::from_secs(1)), false)
+            .unwrap();
+        removed = entries[..len]
+            .iter()
+            .any(|event| unsafe { event.assume_init_ref() }.is_removed());
+        if removed {
+            break;
+        }
+    }
+    assert!(removed);
+    assert!(!poller.contains_socket(socket));
+}
+
+#[cfg(feature = "std")]
+#[test]
+#[cfg_attr(feature = "force-afd", ignore = "AFD doesn't support the edge modes")]
+fn io() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add_io(&s, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    assert!(poller.contains_socket(s.as_raw_socket() as _));
+    poller
+        .modify_io(&s, 2, Interest::WRITABLE, PollMode::Edge)
+        .unwrap();
+    assert_eq!(
+        poller.interest(s.as_raw_socket() as _),
+        Some((2, Interest::WRITABLE, PollMode::Edge))
+    );
+    poller.delete_io(&s).unwrap();
+    assert!(!poller.contains_socket(s.as_raw_socket() as _));
+}
+
+#[test]
+#[cfg_attr(feature = "force-afd", ignore = "AFD doesn't support the edge modes")]
+fn guard() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    {
+        let reg = poller
+            .register(socket, 1, Interest::READABLE, PollMode::Level)
+            .unwrap();
+        assert_eq!(reg.socket(), socket);
+        assert!(poller.contains_socket(socket));
+        reg.modify(2, Interest::WRITABLE, PollMode::Edge).unwrap();
+        assert_eq!(
+            poller.interest(socket),
+            Some((2, Interest::WRITABLE, PollMode::Edge))
+        );
+    }
+    assert!(!poller.contains_socket(socket));
+
+    let reg = poller
+        .register(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    reg.delete().unwrap();
+    assert!(!poller.contains_socket(socket));
+
+    let reg = poller
+        .register(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    assert_eq!(reg.into_raw(), socket);
+    assert!(poller.contains_socket(socket));
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn drop_poller() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    drop(poller);
+
+    // The registration has been removed with the dropped poller.
+    let poller = Poller::new().unwrap();
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    poller.delete(socket).unwrap();
+}
+
+#[cfg(feature = "socket-owners")]
+#[test]
+fn owners() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    let other = Poller::new().unwrap();
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    let e = other
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap_err();
+    assert_eq!(e.0, ERROR_ALREADY_EXISTS);
+
+    poller.delete(socket).unwrap();
+    other
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    other.delete(socket).unwrap();
+
+    // A failed registration doesn't keep the owner.
+    let f = std::fs::File::open("Cargo.toml").unwrap();
+    let socket = std::os::windows::io::AsRawHandle::as_raw_handle(&f) as _;
+    let e = poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap_err();
+    assert_ne!(e.0, ERROR_ALREADY_EXISTS);
+    assert!(!poller.contains_socket(socket));
+    let e = other
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap_err();
+    assert_ne!(e.0, ERROR_ALREADY_EXISTS);
+}
+
+#[cfg(not(feature = "socket-owners"))]
+#[test]
+#[cfg_attr(feature = "force-afd", ignore = "AFD doesn't associate the sockets")]
+fn other_port() {
+    let s = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let socket = s.as_raw_socket() as _;
+
+    let poller = Poller::new().unwrap();
+    let other = Poller::new().unwrap();
+    poller
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap();
+    // Rejected by the system, and not kept.
+    let e = other
+        .add(socket, 1, Interest::READABLE, PollMode::Level)
+        .unwrap_err();
+    assert_eq!(e.0, ERROR_ALREADY_EXISTS);
+    assert!(!other.contains_socket(socket));
+
+    poller.delete(socket).unwrap();
+}
+
+#[test]
+fn quota_exhausted() {
+    for code in [
+        ERROR_NO_SYSTEM_RESOURCES,
+        ERROR_NOT_ENOUGH_QUOTA,
+        ERROR_NOT_ENOUGH_MEMORY,
+        WSAENOBUFS as _,
+    ] {
+        let err = Error::new(code).with_context("ProcessSocketNotifications register");
+        assert!(err.is_quota_exhausted());
+    }
+    assert!(!Error::new(ERROR_NOT_FOUND).is_quota_exhausted());
+
+    // A full poller.
+    let s1 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let s2 = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    let poller = Poller::with_capacity(1).unwrap();
+    poller
+        .add(
+            s1.as_raw_socket() as _,
+            1,
+            Interest::READABLE,
+            PollMode::Level,
+        )
+        .unwrap();
+    let err = poller
+        .add(
+            s2.as_raw_socket() as _,
+            2,
+            Interest::READABLE,
+            PollMode::Level,
+        )
+        .unwrap_err();
+    assert_eq!(err.registration_error(), RegistrationError::Quota);
+    poller.delete(s1.as_raw_socket() as _).unwrap();
+}
@@ -1,6 +1,9 @@
 use std::time::{Duration, Instant};
 
-use wepoll2::Poller;
+use wepoll2::{Poller, Rounding};
+use windows_sys::Win32::{
+    Foundation::FILETIME, System::SystemInformation::GetSystemTimeAsFileTime,
+};
 
 #[test]
 fn twice() {
@@ -40,3 +43,69 @@ fn non_blocking() {
             .unwrap();
     }
 }
+
+#[test]
+fn rounding() {
+    let poller = Poller::new().unwrap();
+    let mut events = Vec::with_capacity(1);
+    let dur = Duration::from_millis(10);
+
+    for rounding in [Rounding::Up, Rounding::Down, Rounding::Nearest] {
+        let start = Instant::now();
+        let n = poller
+            .wait_with_rounding(events.spare_capacity_mut(), Some(dur), false, rounding)
+            .unwrap();
+        assert_eq!(n, 0);
+        assert!(start.elapsed() >= dur, "{:?} < {:?}", start.elapsed(), dur);
+    }
+
+    // Less than a tick is rounded down to no wait.
+    let start = Instant::now();
+    let n = poller
+        .wait_with_rounding(
+            events.spare_capacity_mut(),
+            Some(Duration::from_nanos(99)),
+            false,
+            Rounding::Down,
+        )
+        .unwrap();
+    assert_eq!(n, 0);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn system_time() {
+    let poller = Poller::new().unwrap();
+    let mut events = Vec::with_capacity(1);
+    let dur = Duration::from_millis(100);
+    let margin = Duration::from_millis(10);
+
+    let mut now = unsafe { std::mem::zeroed() };
+    unsafe { GetSystemTimeAsFileTime(&mut now) };
+    let deadline = ((now.dwHighDateTime as u64) << 32 | now.dwLowDateTime as u64)
+        + dur.as_nanos() as u64 / 100;
+    let deadline = FILETIME {
+        dwLowDateTime: deadline as u32,
+        dwHighDateTime: (deadline >> 32) as u32,
+    };
+
+    let start = Instant::now();
+    let n = poller
+        .wait_until_system_time(events.spare_capacity_mut(), deadline, false)
+        .unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(n, 0);
+    assert!(
+        elapsed >= dur - margin,
+        "{:?} < {:?}",
+        elapsed,
+        dur - margin
+    );
+
+    // The deadline has passed.
+    let start = Instant::now();
+    poller
+        .wait_until_system_time(events.spare_capacity_mut(), deadline, false)
+        .unwrap();
+    assert!(start.elapsed() < dur);
+}
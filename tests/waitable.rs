@@ -2,9 +2,10 @@ use std::{
     mem::MaybeUninit,
     os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle},
     ptr::null,
+    time::Duration,
 };
 
-use wepoll2::{Event, Poller};
+use wepoll2::{Interest, PollMode, Poller};
 use windows_sys::Win32::System::Threading::{CreateEventA, SetEvent};
 
 #[test]
@@ -13,9 +14,15 @@ fn poll_event() {
     assert!(!e.is_null());
     let e = unsafe { OwnedHandle::from_raw_handle(e) };
 
-    let mut poller = Poller::new().unwrap();
-    let interest = Event::none(114514).with_readable(true);
-    poller.add_waitable(e.as_raw_handle(), interest).unwrap();
+    let poller = Poller::new().unwrap();
+    poller
+        .add_waitable(
+            e.as_raw_handle(),
+            114514,
+            Interest::READABLE,
+            PollMode::Oneshot,
+        )
+        .unwrap();
 
     let res = unsafe { SetEvent(e.as_raw_handle()) };
     assert!(res != 0);
@@ -29,3 +36,315 @@ fn poll_event() {
 
     poller.delete_waitable(e.as_raw_handle()).unwrap();
 }
+
+#[test]
+fn poll_event_level() {
+    let e = unsafe { CreateEventA(null(), 1, 0, null()) };
+    assert!(!e.is_null());
+    let e = unsafe { OwnedHandle::from_raw_handle(e) };
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add_waitable(
+            e.as_raw_handle(),
+            114514,
+            Interest::READABLE,
+            PollMode::Level,
+        )
+        .unwrap();
+
+    let res = unsafe { SetEvent(e.as_raw_handle()) };
+    assert!(res != 0);
+
+    // The manual-reset event is still signaled, and triggers again.
+    let mut entries = [MaybeUninit::uninit(); 8];
+    for _ in 0..2 {
+        let len = poller.wait(&mut entries, None, false).unwrap();
+        assert_eq!(len, 1);
+        let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+        assert_eq!(event.key(), 114514);
+    }
+
+    poller.delete_waitable(e.as_raw_handle()).unwrap();
+}
+
+#[test]
+fn poll_event_timeout() {
+    let e = unsafe { CreateEventA(null(), 0, 0, null()) };
+    assert!(!e.is_null());
+    let e = unsafe { OwnedHandle::from_raw_handle(e) };
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add_waitable_timeout(
+            e.as_raw_handle(),
+            114514,
+            Interest::READABLE,
+            Duration::from_millis(10),
+        )
+        .unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert_eq!(event.key(), 114514);
+    assert!(event.is_timeout());
+
+    // The event is not delivered after timed out.
+    let res = unsafe { SetEvent(e.as_raw_handle()) };
+    assert!(res != 0);
+    let len = poller
+        .wait(&mut entries, Some(Duration::from_millis(10)), false)
+        .unwrap();
+    assert_eq!(len, 0);
+
+    poller.delete_waitable(e.as_raw_handle()).unwrap();
+}
+
+#[test]
+fn poll_timer() {
+    let poller = Poller::new().unwrap();
+    let timer = poller
+        .add_timer(
+            114514,
+            Duration::from_millis(10),
+            Some(Duration::from_millis(10)),
+        )
+        .unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    for _ in 0..3 {
+        let len = poller.wait(&mut entries, None, false).unwrap();
+        assert_eq!(len, 1);
+        let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+        assert_eq!(event.key(), 114514);
+        assert!(event.is_readable());
+    }
+
+    poller.delete_waitable(timer).unwrap();
+}
+
+#[test]
+fn poll_events_batch() {
+    let events = (0..2)
+        .map(|_| {
+            let e = unsafe { CreateEventA(null(), 0, 0, null()) };
+            assert!(!e.is_null());
+            unsafe { OwnedHandle::from_raw_handle(e) }
+        })
+        .collect::<Vec<_>>();
+    let waitables = events
+        .iter()
+        .enumerate()
+        .map(|(key, e)| (e.as_raw_handle(), key, Interest::READABLE))
+        .collect::<Vec<_>>();
+
+    let poller = Poller::new().unwrap();
+    for round in 0..2 {
+        let results = poller.add_waitables(&waitables, PollMode::Oneshot).unwrap();
+        assert!(results.iter().all(Result::is_ok));
+
+        for e in &events {
+            let res = unsafe { SetEvent(e.as_raw_handle()) };
+            assert!(res != 0);
+        }
+
+        let mut entries = [MaybeUninit::uninit(); 8];
+        let mut keys = vec![];
+        while keys.len() < 2 {
+            let len = poller.wait(&mut entries, None, false).unwrap();
+            keys.extend(
+                entries[..len]
+                    .iter()
+                    .map(|e| unsafe { e.assume_init_ref() }.key()),
+            );
+        }
+        keys.sort();
+        assert_eq!(keys, [0, 1], "round {round}");
+
+        // The packets are reused in the next round.
+        for e in &events {
+            poller.delete_waitable(e.as_raw_handle()).unwrap();
+        }
+    }
+}
+
+#[test]
+fn poll_event_signaled() {
+    let e = unsafe { CreateEventA(null(), 0, 0, null()) };
+    assert!(!e.is_null());
+    let e = unsafe { OwnedHandle::from_raw_handle(e) };
+
+    // Signal the event before it is added.
+    let res = unsafe { SetEvent(e.as_raw_handle()) };
+    assert!(res != 0);
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add_waitable(e.as_raw_handle(), 1, Interest::READABLE, PollMode::Oneshot)
+        .unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller
+        .wait(&mut entries, Some(Duration::from_secs(1)), false)
+        .unwrap();
+    assert_eq!(len, 1);
+    let len = poller
+        .wait(&mut entries, Some(Duration::ZERO), false)
+        .unwrap();
+    assert_eq!(len, 0);
+
+    poller.delete_waitable(e.as_raw_handle()).unwrap();
+}
+
+#[test]
+fn poll_event_status() {
+    let e = unsafe { CreateEventA(null(), 0, 0, null()) };
+    assert!(!e.is_null());
+    let e = unsafe { OwnedHandle::from_raw_handle(e) };
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add_waitable_with_status(
+            e.as_raw_handle(),
+            114514,
+            Interest::READABLE,
+            PollMode::Oneshot,
+            0x1234,
+        )
+        .unwrap();
+
+    let res = unsafe { SetEvent(e.as_raw_handle()) };
+    assert!(res != 0);
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert_eq!(event.key(), 114514);
+    assert_eq!(event.status(), 0x1234);
+    assert!(event.is_readable());
+
+    poller.delete_waitable(e.as_raw_handle()).unwrap();
+}
+
+#[test]
+fn poll_event_status_reposted() {
+    let e = unsafe { CreateEventA(null(), 0, 0, null()) };
+    assert!(!e.is_null());
+    let e = unsafe { OwnedHandle::from_raw_handle(e) };
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add_waitable_with_status(
+            e.as_raw_handle(),
+            114514,
+            Interest::READABLE,
+            PollMode::Oneshot,
+            0x1234,
+        )
+        .unwrap();
+
+    let res = unsafe { SetEvent(e.as_raw_handle()) };
+    assert!(res != 0);
+
+    // Both probing and flushing other keys post the entry back.
+    assert!(poller.has_pending().unwrap());
+    assert_eq!(poller.flush_pending(1).unwrap(), 0);
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller.wait(&mut entries, None, false).unwrap();
+    assert_eq!(len, 1);
+    let event = unsafe { MaybeUninit::assume_init_ref(&entries[0]) };
+    assert_eq!(event.key(), 114514);
+    assert_eq!(event.status(), 0x1234);
+    assert!(event.is_readable());
+
+    poller.delete_waitable(e.as_raw_handle()).unwrap();
+}
+
+#[test]
+fn modify_waitable_unchanged() {
+    let e = unsafe { CreateEventA(null(), 0, 0, null()) };
+    assert!(!e.is_null());
+    let e = unsafe { OwnedHandle::from_raw_handle(e) };
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add_waitable(e.as_raw_handle(), 1, Interest::READABLE, PollMode::Oneshot)
+        .unwrap();
+    // Nothing changes, and the registration is kept.
+    poller
+        .modify_waitable(e.as_raw_handle(), Interest::READABLE, PollMode::Oneshot)
+        .unwrap();
+
+    let mut entries = [MaybeUninit::uninit(); 8];
+    for _ in 0..2 {
+        let res = unsafe { SetEvent(e.as_raw_handle()) };
+        assert!(res != 0);
+        let len = poller
+            .wait(&mut entries, Some(Duration::from_secs(1)), false)
+            .unwrap();
+        assert_eq!(len, 1);
+
+        // The event has been received, and the same interest re-arms it.
+        poller
+            .modify_waitable(e.as_raw_handle(), Interest::READABLE, PollMode::Oneshot)
+            .unwrap();
+    }
+
+    poller.delete_waitable(e.as_raw_handle()).unwrap();
+}
+
+#[test]
+fn modify_waitable_signaled() {
+    let e = unsafe { CreateEventA(null(), 1, 0, null()) };
+    assert!(!e.is_null());
+    let e = unsafe { OwnedHandle::from_raw_handle(e) };
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add_waitable(e.as_raw_handle(), 1, Interest::READABLE, PollMode::Oneshot)
+        .unwrap();
+    let res = unsafe { SetEvent(e.as_raw_handle()) };
+    assert!(res != 0);
+
+    // The completion has been queued, and it is not delivered twice.
+    poller
+        .modify_waitable(e.as_raw_handle(), Interest::ALL, PollMode::Oneshot)
+        .unwrap();
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller
+        .wait(&mut entries, Some(Duration::from_secs(1)), false)
+        .unwrap();
+    assert_eq!(len, 1);
+    let len = poller
+        .wait(&mut entries, Some(Duration::ZERO), false)
+        .unwrap();
+    assert_eq!(len, 0);
+
+    poller.delete_waitable(e.as_raw_handle()).unwrap();
+}
+
+#[test]
+fn delete_waitable_signaled() {
+    let e = unsafe { CreateEventA(null(), 1, 0, null()) };
+    assert!(!e.is_null());
+    let e = unsafe { OwnedHandle::from_raw_handle(e) };
+
+    let poller = Poller::new().unwrap();
+    poller
+        .add_waitable(e.as_raw_handle(), 1, Interest::READABLE, PollMode::Oneshot)
+        .unwrap();
+    let res = unsafe { SetEvent(e.as_raw_handle()) };
+    assert!(res != 0);
+
+    // The queued completion is removed with the waitable.
+    poller.delete_waitable(e.as_raw_handle()).unwrap();
+    let mut entries = [MaybeUninit::uninit(); 8];
+    let len = poller
+        .wait(&mut entries, Some(Duration::ZERO), false)
+        .unwrap();
+    assert_eq!(len, 0);
+}